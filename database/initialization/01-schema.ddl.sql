@@ -1,18 +1,27 @@
 CREATE SCHEMA records;
 
-CREATE TABLE records.bme280 (
+-- The ids of the records of every sensor, keeping ids unique across the sensor tables.
+CREATE TABLE records.record_ids (
     id uuid PRIMARY KEY,
+    owner_id uuid NOT NULL
+);
+
+CREATE TABLE records.bme280 (
+    id uuid PRIMARY KEY REFERENCES records.record_ids(id) ON DELETE CASCADE,
     temperature real NOT NULL,
     pressure real NOT NULL,
     humidity real NOT NULL,
-    timestamp timestamp with time zone NOT NULL
+    timestamp timestamp with time zone NOT NULL,
+    api_key_id uuid,
+    owner_id uuid NOT NULL
 );
 
 CREATE TABLE records.ds18b20 (
-    id uuid PRIMARY KEY,
+    id uuid PRIMARY KEY REFERENCES records.record_ids(id) ON DELETE CASCADE,
     device_name text NOT NULL,
     raw_reading integer NOT NULL,
-    timestamp timestamp with time zone NOT NULL
+    timestamp timestamp with time zone NOT NULL,
+    owner_id uuid NOT NULL
 );
 
 CREATE SCHEMA auth;
@@ -20,12 +29,166 @@ CREATE SCHEMA auth;
 CREATE TABLE auth.users (
     id uuid PRIMARY KEY,
     username text NOT NULL UNIQUE,
-    password text NOT NULL
+    password text NOT NULL,
+    role text NOT NULL DEFAULT 'member' CHECK (role IN ('admin', 'member', 'viewer')),
+    disabled boolean NOT NULL DEFAULT false,
+    -- Registered while approval is required, and not yet approved by an admin.
+    pending boolean NOT NULL DEFAULT false
+);
+
+-- Single-use codes letting someone register an account with the role of the invite.
+CREATE TABLE auth.invites (
+    id uuid PRIMARY KEY,
+    -- HMAC-SHA256 of the code keyed with the API_KEY_SECRET of the server.
+    code_hash text NOT NULL UNIQUE,
+    role text NOT NULL CHECK (role IN ('admin', 'member', 'viewer')),
+    created_by uuid REFERENCES auth.users(id) ON DELETE SET NULL,
+    created_at timestamp with time zone NOT NULL,
+    used_by uuid REFERENCES auth.users(id) ON DELETE SET NULL,
+    used_at timestamp with time zone
+);
+
+-- TOTP (RFC 6238) second factors of the users who have enrolled one.
+CREATE TABLE auth.totp (
+    user_id uuid PRIMARY KEY REFERENCES auth.users(id) ON DELETE CASCADE,
+    -- Base32 secret shared with the authenticator app of the user.
+    secret text NOT NULL,
+    -- The time step of the last accepted code, so that a code cannot be used twice.
+    last_step bigint,
+    -- Codes entered since the last accepted code or lockout, counted before they are checked.
+    failed_attempts integer NOT NULL DEFAULT 0,
+    -- No code is checked until then, not even a right one.
+    locked_until timestamp with time zone,
+    enabled_at timestamp with time zone NOT NULL DEFAULT now()
+);
+
+-- Single-use codes logging in in place of a TOTP code.
+CREATE TABLE auth.recovery_codes (
+    user_id uuid NOT NULL REFERENCES auth.users(id) ON DELETE CASCADE,
+    -- HMAC-SHA256 of the code keyed with the API_KEY_SECRET of the server.
+    code_hash text NOT NULL,
+    PRIMARY KEY (user_id, code_hash)
+);
+
+-- Single-use tokens issued by an admin, letting a user set a new password.
+CREATE TABLE auth.password_resets (
+    id uuid PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES auth.users(id) ON DELETE CASCADE,
+    -- HMAC-SHA256 of the token keyed with the API_KEY_SECRET of the server.
+    token_hash text NOT NULL UNIQUE,
+    created_by uuid REFERENCES auth.users(id) ON DELETE SET NULL,
+    expires_at timestamp with time zone NOT NULL
 );
 
 CREATE TABLE auth.api_keys (
     id uuid PRIMARY KEY,
-    name text NOT NULL UNIQUE,
+    name text NOT NULL,
     owner_id uuid REFERENCES auth.users(id) ON DELETE CASCADE,
-    token text NOT NULL UNIQUE
+    -- HMAC-SHA256 of the token keyed with the API_KEY_SECRET of the server. The token itself is not stored.
+    token_hash text NOT NULL UNIQUE,
+    token_prefix text NOT NULL,
+    scopes text[] NOT NULL CHECK (scopes <@ ARRAY['records:read', 'records:write', 'devices:write']),
+    expires_at timestamptz,
+    revoked_at timestamptz,
+    UNIQUE (owner_id, name),
+    UNIQUE (id, owner_id)
+);
+
+-- Records are owned by the owner of the API key that submitted them.
+ALTER TABLE records.record_ids ADD FOREIGN KEY (owner_id) REFERENCES auth.users(id) ON DELETE CASCADE;
+ALTER TABLE records.bme280 ADD FOREIGN KEY (owner_id) REFERENCES auth.users(id) ON DELETE CASCADE;
+ALTER TABLE records.ds18b20 ADD FOREIGN KEY (owner_id) REFERENCES auth.users(id) ON DELETE CASCADE;
+CREATE INDEX ON records.bme280 (owner_id, timestamp);
+CREATE INDEX ON records.ds18b20 (owner_id, timestamp);
+
+-- An owner letting a grantee read all of the owner's records.
+CREATE TABLE auth.record_shares (
+    owner_id uuid NOT NULL REFERENCES auth.users(id) ON DELETE CASCADE,
+    grantee_id uuid NOT NULL REFERENCES auth.users(id) ON DELETE CASCADE,
+    PRIMARY KEY (owner_id, grantee_id),
+    CHECK (owner_id <> grantee_id)
+);
+
+-- A device only applies to the records of its owner, and only with an API key of its owner.
+CREATE TABLE records.devices (
+    id uuid PRIMARY KEY,
+    owner_id uuid NOT NULL REFERENCES auth.users(id) ON DELETE CASCADE,
+    sensor text NOT NULL CHECK (sensor IN ('bme280', 'ds18b20')),
+    serial text,
+    api_key_id uuid UNIQUE,
+    name text NOT NULL,
+    location text NOT NULL DEFAULT '',
+    description text NOT NULL DEFAULT '',
+    UNIQUE (owner_id, serial),
+    UNIQUE (id, owner_id),
+    FOREIGN KEY (api_key_id, owner_id) REFERENCES auth.api_keys(id, owner_id) ON DELETE SET NULL (api_key_id),
+    CHECK (sensor <> 'ds18b20' OR (serial IS NOT NULL AND api_key_id IS NULL)),
+    -- A BME280 device is identified by its API key, which is only missing once the key is deleted.
+    CHECK (sensor <> 'bme280' OR serial IS NULL)
+);
+
+CREATE TABLE records.calibrations (
+    id uuid PRIMARY KEY,
+    device_id uuid NOT NULL REFERENCES records.devices(id) ON DELETE CASCADE,
+    quantity text NOT NULL CHECK (quantity IN ('temperature', 'pressure', 'humidity')),
+    gain double precision NOT NULL DEFAULT 1,
+    "offset" double precision NOT NULL DEFAULT 0,
+    valid_from timestamp with time zone NOT NULL,
+    valid_to timestamp with time zone,
+    CHECK (valid_to IS NULL OR valid_to > valid_from)
+);
+
+CREATE TABLE records.last_seen (
+    owner_id uuid NOT NULL REFERENCES auth.users(id) ON DELETE CASCADE,
+    device_name text,
+    api_key_id uuid UNIQUE REFERENCES auth.api_keys(id) ON DELETE CASCADE,
+    last_seen timestamp with time zone NOT NULL,
+    UNIQUE (owner_id, device_name),
+    CHECK ((device_name IS NULL) <> (api_key_id IS NULL))
+);
+
+-- An alert rule belongs to the owner of its device.
+CREATE TABLE records.alert_rules (
+    id uuid PRIMARY KEY,
+    owner_id uuid NOT NULL,
+    name text NOT NULL,
+    device_id uuid NOT NULL,
+    quantity text NOT NULL CHECK (quantity IN ('temperature', 'pressure', 'humidity')),
+    comparison text NOT NULL CHECK (comparison IN ('above', 'below')),
+    threshold double precision NOT NULL,
+    duration_seconds integer NOT NULL DEFAULT 0 CHECK (duration_seconds >= 0),
+    webhook_url text,
+    firing boolean NOT NULL DEFAULT false,
+    breached_since timestamp with time zone,
+    FOREIGN KEY (device_id, owner_id) REFERENCES records.devices(id, owner_id) ON DELETE CASCADE
+);
+
+CREATE TABLE records.alert_events (
+    id uuid PRIMARY KEY,
+    rule_id uuid NOT NULL REFERENCES records.alert_rules(id) ON DELETE CASCADE,
+    kind text NOT NULL CHECK (kind IN ('fired', 'resolved')),
+    value double precision NOT NULL,
+    record_id uuid NOT NULL,
+    timestamp timestamp with time zone NOT NULL
+);
+
+CREATE TABLE records.webhooks (
+    id uuid PRIMARY KEY,
+    owner_id uuid NOT NULL REFERENCES auth.users(id) ON DELETE CASCADE,
+    url text NOT NULL,
+    secret text NOT NULL,
+    sensor text[] NOT NULL DEFAULT '{}',
+    device_name text[] NOT NULL DEFAULT '{}'
+);
+
+CREATE TABLE records.webhook_deliveries (
+    id uuid PRIMARY KEY,
+    delivery_id uuid NOT NULL,
+    webhook_id uuid NOT NULL REFERENCES records.webhooks(id) ON DELETE CASCADE,
+    record_id uuid NOT NULL,
+    attempt integer NOT NULL,
+    status_code integer,
+    error text,
+    succeeded boolean NOT NULL,
+    attempted_at timestamp with time zone NOT NULL
 );
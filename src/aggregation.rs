@@ -0,0 +1,292 @@
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::types::PgInterval;
+use sqlx::types::chrono::{DateTime, Utc};
+
+const MICROSECONDS_PER_SECOND: i64 = 1_000_000;
+/// The most time buckets a single aggregation may span.
+pub(crate) const MAX_BUCKETS: i64 = 5000;
+
+static TOO_MANY_BUCKETS: std::sync::LazyLock<String> = std::sync::LazyLock::new(|| {
+    format!("the time range must not span more than {MAX_BUCKETS} buckets; use a wider bucket or a shorter range")
+});
+
+/// Filter for aggregating records into time buckets.
+///
+/// The time range is required and bounded to [`MAX_BUCKETS`] buckets, so every bucket of the
+/// range is always returned in full.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct AggregateFilter {
+    pub bucket: Bucket,
+    #[serde(rename = "fn", default)]
+    pub functions: Functions,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// Only include DS18B20 records from these devices. The parameter may be repeated.
+    #[serde(default)]
+    pub device_name: Vec<String>,
+}
+
+impl AggregateFilter {
+    /// Rejects empty time ranges and ranges spanning more than [`MAX_BUCKETS`] buckets.
+    pub fn validate(&self) -> Result<(), AggregationError> {
+        if self.to <= self.from {
+            return Err(AggregationError::InvalidRange);
+        }
+        if self.bucket_count() > MAX_BUCKETS {
+            return Err(AggregationError::TooManyBuckets);
+        }
+        Ok(())
+    }
+
+    /// The number of buckets, aligned to the Unix epoch, that the time range touches.
+    fn bucket_count(&self) -> i64 {
+        let first = self.from.timestamp().div_euclid(self.bucket.seconds);
+        let last = (self.to.timestamp() - 1).div_euclid(self.bucket.seconds);
+        last - first + 1
+    }
+}
+
+/// The width of a time bucket, e.g. `15m` or `1h`.
+///
+/// The width is a positive integer followed by one of the units `s` (seconds), `m` (minutes),
+/// `h` (hours), `d` (days) or `w` (weeks). Buckets are aligned to the Unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub(crate) struct Bucket {
+    seconds: i64,
+}
+
+impl Bucket {
+    pub fn interval(&self) -> PgInterval {
+        PgInterval {
+            months: 0,
+            days: 0,
+            microseconds: self.seconds * MICROSECONDS_PER_SECOND,
+        }
+    }
+}
+
+impl TryFrom<String> for Bucket {
+    type Error = AggregationError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let seconds = parse_seconds(&value).ok_or(AggregationError::InvalidBucket)?;
+        Ok(Bucket { seconds })
+    }
+}
+
+/// Parses a duration given as a positive integer followed by one of the units `s` (seconds),
+/// `m` (minutes), `h` (hours), `d` (days) or `w` (weeks), e.g. `15m`, into seconds.
+///
+/// Durations too long to be represented in microseconds are rejected.
+pub(crate) fn parse_seconds(value: &str) -> Option<i64> {
+    let split = value.len().checked_sub(1)?;
+    if !value.is_char_boundary(split) {
+        return None;
+    }
+    let (count, unit) = value.split_at(split);
+    let count = count.parse::<i64>().ok()?;
+    let unit_seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+
+    if count <= 0 {
+        return None;
+    }
+
+    count
+        .checked_mul(unit_seconds)
+        .filter(|seconds| seconds.checked_mul(MICROSECONDS_PER_SECOND).is_some())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AggregateFunction {
+    Avg,
+    Min,
+    Max,
+    Count,
+}
+
+impl TryFrom<&str> for AggregateFunction {
+    type Error = AggregationError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.trim() {
+            "avg" => Ok(AggregateFunction::Avg),
+            "min" => Ok(AggregateFunction::Min),
+            "max" => Ok(AggregateFunction::Max),
+            "count" => Ok(AggregateFunction::Count),
+            _ => Err(AggregationError::InvalidFunction),
+        }
+    }
+}
+
+/// A comma separated list of aggregate functions, e.g. `avg,min,max`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub(crate) struct Functions(Vec<AggregateFunction>);
+
+impl Functions {
+    pub fn contains(&self, function: AggregateFunction) -> bool {
+        self.0.contains(&function)
+    }
+}
+
+impl Default for Functions {
+    fn default() -> Self {
+        Functions(vec![AggregateFunction::Avg, AggregateFunction::Min, AggregateFunction::Max])
+    }
+}
+
+impl TryFrom<String> for Functions {
+    type Error = AggregationError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let functions = value
+            .split(',')
+            .map(AggregateFunction::try_from)
+            .collect::<Result<Vec<AggregateFunction>, AggregationError>>()?;
+        Ok(Functions(functions))
+    }
+}
+
+/// The aggregated values of a single metric within a single time bucket.
+///
+/// Only the values of the requested aggregate functions are included.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct AggregateRow {
+    pub bucket: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_name: Option<String>,
+    pub metric: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<i64>,
+}
+
+impl AggregateRow {
+    /// Keeps only the values of the given aggregate functions.
+    pub fn select(self, functions: &Functions) -> Self {
+        Self {
+            avg: self.avg.filter(|_| functions.contains(AggregateFunction::Avg)),
+            min: self.min.filter(|_| functions.contains(AggregateFunction::Min)),
+            max: self.max.filter(|_| functions.contains(AggregateFunction::Max)),
+            count: self.count.filter(|_| functions.contains(AggregateFunction::Count)),
+            ..self
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AggregationError {
+    InvalidBucket,
+    InvalidFunction,
+    InvalidRange,
+    TooManyBuckets,
+}
+
+impl AggregationError {
+    pub fn message(&self) -> &'static str {
+        match self {
+            AggregationError::InvalidBucket => "bucket must be a positive integer followed by one of the units s, m, h, d or w",
+            AggregationError::InvalidFunction => "fn must be a comma separated list of avg, min, max and count",
+            AggregationError::InvalidRange => "to must be later than from",
+            AggregationError::TooManyBuckets => TOO_MANY_BUCKETS.as_str(),
+        }
+    }
+}
+
+impl std::fmt::Display for AggregationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for AggregationError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_from_string() {
+        assert_eq!(Bucket::try_from("30s".to_string()).unwrap().seconds, 30);
+        assert_eq!(Bucket::try_from("15m".to_string()).unwrap().seconds, 15 * 60);
+        assert_eq!(Bucket::try_from("1h".to_string()).unwrap().seconds, 60 * 60);
+        assert_eq!(Bucket::try_from("2d".to_string()).unwrap().seconds, 2 * 24 * 60 * 60);
+        assert_eq!(Bucket::try_from("1w".to_string()).unwrap().seconds, 7 * 24 * 60 * 60);
+    }
+
+    #[test]
+    fn test_bucket_rejects_invalid_widths() {
+        for invalid in ["", "h", "0h", "-1h", "1y", "1.5h", "1 h", "1°", "99999999999999999w"] {
+            assert!(Bucket::try_from(invalid.to_string()).is_err(), "accepted '{invalid}'");
+        }
+    }
+
+    fn filter(bucket: &str, from: i64, to: i64) -> AggregateFilter {
+        AggregateFilter {
+            bucket: Bucket::try_from(bucket.to_string()).unwrap(),
+            functions: Functions::default(),
+            from: DateTime::from_timestamp(from, 0).unwrap(),
+            to: DateTime::from_timestamp(to, 0).unwrap(),
+            device_name: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_bucket_count() {
+        assert_eq!(filter("1h", 0, 3600).bucket_count(), 1);
+        assert_eq!(filter("1h", 1800, 3601).bucket_count(), 2);
+        assert_eq!(filter("1h", 0, 31 * 24 * 3600).bucket_count(), 31 * 24);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(filter("1h", 0, 31 * 24 * 3600).validate().is_ok());
+        assert!(filter("1h", 0, MAX_BUCKETS * 3600).validate().is_ok());
+        assert!(matches!(filter("1h", 0, MAX_BUCKETS * 3600 + 1).validate(), Err(AggregationError::TooManyBuckets)));
+        assert!(matches!(filter("1h", 3600, 3600).validate(), Err(AggregationError::InvalidRange)));
+        assert!(matches!(filter("1h", 3600, 0).validate(), Err(AggregationError::InvalidRange)));
+        assert!(AggregationError::TooManyBuckets.message().contains(&MAX_BUCKETS.to_string()));
+    }
+
+    #[test]
+    fn test_functions_from_string() {
+        let functions = Functions::try_from("avg,max".to_string()).unwrap();
+        assert!(functions.contains(AggregateFunction::Avg));
+        assert!(functions.contains(AggregateFunction::Max));
+        assert!(!functions.contains(AggregateFunction::Min));
+
+        assert!(Functions::try_from("avg,median".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_select() {
+        let row = AggregateRow {
+            bucket: DateTime::from_timestamp(0, 0).unwrap(),
+            device_name: None,
+            metric: "temperature",
+            avg: Some(21.5),
+            min: Some(20.0),
+            max: Some(23.0),
+            count: Some(12),
+        };
+
+        let row = row.select(&Functions::try_from("min,count".to_string()).unwrap());
+        assert_eq!(row.avg, None);
+        assert_eq!(row.min, Some(20.0));
+        assert_eq!(row.max, None);
+        assert_eq!(row.count, Some(12));
+    }
+}
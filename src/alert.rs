@@ -0,0 +1,346 @@
+use crate::calibration::Quantity;
+use crate::device::SensorType;
+use crate::repository::Repository;
+use crate::webhook;
+use rerec::record::Record;
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub(crate) enum Comparison {
+    Above,
+    Below,
+}
+
+impl Comparison {
+    fn breached(self, value: f64, threshold: f64) -> bool {
+        match self {
+            Comparison::Above => value > threshold,
+            Comparison::Below => value < threshold,
+        }
+    }
+}
+
+/// A rule raising an alert when a quantity measured by a device stays above or below a
+/// threshold for a while, e.g. a greenhouse probe below 2 °C for 10 minutes.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub(crate) struct AlertRule {
+    pub id: Uuid,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub details: AlertRuleDetails,
+    /// Whether the alert has fired and not yet resolved.
+    pub firing: bool,
+    /// The timestamp of the first record of the current breach of the threshold, if any.
+    pub breached_since: Option<DateTime<Utc>>,
+}
+
+impl AlertRule {
+    pub fn new(details: AlertRuleDetails) -> Self {
+        Self { id: Uuid::new_v4(), details, firing: false, breached_since: None }
+    }
+
+    /// Updates the state of the rule with a value measured at `timestamp`, and returns the kind
+    /// of event if the alert fires or resolves.
+    ///
+    /// The alert fires once the threshold has been breached by every value for at least the
+    /// duration of the rule, and resolves on the first value not breaching it. Values are
+    /// taken in the order their records arrive.
+    pub fn observe(&mut self, value: f64, timestamp: DateTime<Utc>) -> Option<AlertEventKind> {
+        let details = &self.details;
+        if !details.comparison.breached(value, details.threshold) {
+            self.breached_since = None;
+            return std::mem::take(&mut self.firing).then_some(AlertEventKind::Resolved);
+        }
+
+        let breached_since = *self.breached_since.get_or_insert(timestamp);
+        if self.firing || (timestamp - breached_since).num_seconds() < i64::from(details.duration_seconds) {
+            return None;
+        }
+
+        self.firing = true;
+        Some(AlertEventKind::Fired)
+    }
+}
+
+/// The editable properties of an alert rule.
+///
+/// Temperatures are compared in degrees Celsius. The values compared are calibrated if a
+/// calibration of the device applies.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub(crate) struct AlertRuleDetails {
+    pub name: String,
+    pub device_id: Uuid,
+    pub quantity: Quantity,
+    pub comparison: Comparison,
+    pub threshold: f64,
+    /// For how long the threshold must be breached before the alert fires.
+    #[serde(default)]
+    pub duration_seconds: i32,
+    /// Where to POST the event when the alert fires or resolves.
+    pub webhook_url: Option<String>,
+}
+
+impl AlertRuleDetails {
+    /// Checks that the rule is usable for a device of the given sensor type.
+    pub fn validate(&self, sensor: SensorType) -> Result<(), &'static str> {
+        if self.name.trim().is_empty() {
+            return Err("alert rule name must not be empty");
+        }
+
+        if !self.threshold.is_finite() {
+            return Err("threshold must be a finite number");
+        }
+
+        if self.duration_seconds < 0 {
+            return Err("duration_seconds must not be negative");
+        }
+
+        if let Some(webhook_url) = &self.webhook_url {
+            webhook::check_url(webhook_url)?;
+        }
+
+        if sensor == SensorType::Ds18b20 && self.quantity != Quantity::Temperature {
+            return Err("a DS18B20 device only measures temperature");
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub(crate) enum AlertEventKind {
+    Fired,
+    Resolved,
+}
+
+/// An alert firing or resolving, caused by the value of a record.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub(crate) struct AlertEvent {
+    pub id: Uuid,
+    pub rule_id: Uuid,
+    pub kind: AlertEventKind,
+    pub value: f64,
+    pub record_id: Uuid,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl AlertEvent {
+    pub fn new(rule: &AlertRule, kind: AlertEventKind, value: f64, record: &Record) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            rule_id: rule.id,
+            kind,
+            value,
+            record_id: record.id(),
+            timestamp: record.timestamp(),
+        }
+    }
+}
+
+/// An alert event as listed in the history, along with the names of its rule and device.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub(crate) struct AlertHistoryEntry {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub event: AlertEvent,
+    pub rule_name: String,
+    pub device_name: String,
+}
+
+/// The JSON payload POSTed to the webhook of a rule.
+#[derive(Debug, Serialize)]
+struct WebhookPayload<'a> {
+    event: &'a AlertEvent,
+    rule: &'a AlertRule,
+}
+
+/// How many batches of records may wait for evaluation before further batches are dropped.
+const CAPACITY: usize = 1024;
+
+/// Newly committed records, in the order they were submitted with one API key.
+struct Batch {
+    records: Vec<Record>,
+    owner_id: Uuid,
+    api_key_id: Uuid,
+}
+
+/// Evaluates the alert rules of newly committed records and notifies their webhooks, in the
+/// background.
+///
+/// Batches are evaluated one after another in the order they were queued, so the rules of a
+/// device observe its records in order.
+#[derive(Clone)]
+pub(crate) struct AlertEvaluator {
+    sender: mpsc::Sender<Batch>,
+}
+
+impl AlertEvaluator {
+    pub fn start(repository: Repository) -> Self {
+        let (sender, receiver) = mpsc::channel(CAPACITY);
+        tokio::spawn(evaluate(repository, receiver));
+        Self { sender }
+    }
+
+    /// Queues committed records of the owner of the API key for evaluation without waiting for
+    /// them to be evaluated.
+    pub fn enqueue(&self, records: &[Record], owner_id: Uuid, api_key_id: Uuid) {
+        if records.is_empty() {
+            return;
+        }
+
+        let batch = Batch { records: records.to_vec(), owner_id, api_key_id };
+        if let Err(error) = self.sender.try_send(batch) {
+            tracing::warn!("Not evaluating alert rules of {} records: {error}", records.len());
+        }
+    }
+}
+
+async fn evaluate(repository: Repository, mut receiver: mpsc::Receiver<Batch>) {
+    let client = webhook::client();
+
+    while let Some(Batch { records, owner_id, api_key_id }) = receiver.recv().await {
+        let calibrations = match repository.get_record_calibrations(&records).await {
+            Ok(calibrations) => calibrations,
+            Err(error) => {
+                tracing::warn!("Unable to evaluate alert rules: {error}");
+                continue;
+            }
+        };
+
+        for record in &records {
+            let events = match repository.observe_alert_rules(record, owner_id, api_key_id, &calibrations).await {
+                Ok(events) => events,
+                Err(error) => {
+                    tracing::warn!("Unable to evaluate alert rules of record {}: {error}", record.id());
+                    continue;
+                }
+            };
+
+            for (rule, event) in events {
+                if let Some(webhook_url) = rule.details.webhook_url.clone() {
+                    // Saving the rule checked the URL, but the check may have been tightened
+                    // since, and the client only vets host names, not IP literals.
+                    if let Err(error) = webhook::check_url(&webhook_url) {
+                        tracing::warn!("Not notifying webhook of alert rule {}: {error}", rule.id);
+                        continue;
+                    }
+                    let client = client.clone();
+                    tokio::spawn(async move {
+                        let payload = WebhookPayload { event: &event, rule: &rule };
+                        if let Err(error) = post_webhook(&client, &webhook_url, &payload).await {
+                            tracing::warn!("Unable to notify webhook of alert rule {}: {error}", rule.id);
+                        }
+                    });
+                }
+            }
+        }
+    }
+}
+
+async fn post_webhook(client: &reqwest::Client, url: &str, payload: &WebhookPayload<'_>) -> reqwest::Result<()> {
+    client.post(url).json(payload).send().await?.error_for_status()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use rerec::Reading;
+    use rerec::ds18b20::DS18B20;
+    use serde_json::{json, Value};
+
+    fn rule(comparison: Comparison, threshold: f64, duration_seconds: i32) -> AlertRule {
+        AlertRule::new(AlertRuleDetails {
+            name: "Greenhouse frost".to_string(),
+            device_id: Uuid::new_v4(),
+            quantity: Quantity::Temperature,
+            comparison,
+            threshold,
+            duration_seconds,
+            webhook_url: None,
+        })
+    }
+
+    fn at(minutes: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1772184765 + minutes * 60, 0).unwrap()
+    }
+
+    #[test]
+    fn test_fire_after_duration_and_resolve() {
+        let mut rule = rule(Comparison::Below, 2.0, 10 * 60);
+
+        assert_eq!(rule.observe(1.5, at(0)), None);
+        assert_eq!(rule.observe(1.2, at(5)), None);
+        assert_eq!(rule.observe(1.1, at(10)), Some(AlertEventKind::Fired));
+        assert_eq!(rule.observe(0.9, at(15)), None);
+        assert_eq!(rule.observe(2.5, at(20)), Some(AlertEventKind::Resolved));
+        assert!(!rule.firing);
+        assert_eq!(rule.breached_since, None);
+    }
+
+    #[test]
+    fn test_interrupted_breach_does_not_fire() {
+        let mut rule = rule(Comparison::Below, 2.0, 10 * 60);
+
+        assert_eq!(rule.observe(1.5, at(0)), None);
+        assert_eq!(rule.observe(2.1, at(5)), None);
+        assert_eq!(rule.observe(1.5, at(10)), None);
+        assert_eq!(rule.breached_since, Some(at(10)));
+    }
+
+    #[test]
+    fn test_fire_immediately_without_duration() {
+        let mut rule = rule(Comparison::Above, 80.0, 0);
+
+        assert_eq!(rule.observe(80.0, at(0)), None);
+        assert_eq!(rule.observe(80.5, at(1)), Some(AlertEventKind::Fired));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut rule = rule(Comparison::Above, 80.0, 0);
+        rule.details.quantity = Quantity::Humidity;
+        assert!(rule.details.validate(SensorType::Bme280).is_ok());
+        assert!(rule.details.validate(SensorType::Ds18b20).is_err());
+
+        rule.details.webhook_url = Some("ftp://example.com/alerts".to_string());
+        assert!(rule.details.validate(SensorType::Bme280).is_err());
+
+        rule.details.webhook_url = Some("http://169.254.169.254/latest/meta-data".to_string());
+        assert!(rule.details.validate(SensorType::Bme280).is_err());
+
+        rule.details.webhook_url = Some("https://example.com/alerts".to_string());
+        assert!(rule.details.validate(SensorType::Bme280).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_post_webhook() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let receiver_app = Router::new().route("/alerts", post(move |Json(payload): Json<Value>| async move {
+            sender.send(payload).unwrap();
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, receiver_app).await });
+
+        let rule = rule(Comparison::Below, 2.0, 0);
+        let record = Record::new(Uuid::new_v4(), at(0), Reading::DS18B20(DS18B20::new("0000003e33d5".to_string(), 1500)));
+        let event = AlertEvent::new(&rule, AlertEventKind::Fired, 1.5, &record);
+        post_webhook(&webhook::client(), &url, &WebhookPayload { event: &event, rule: &rule }).await.unwrap();
+
+        let payload = receiver.recv().await.unwrap();
+        assert_eq!(payload["event"]["kind"], json!("fired"));
+        assert_eq!(payload["event"]["value"], json!(1.5));
+        assert_eq!(payload["event"]["record_id"], json!(record.id()));
+        assert_eq!(payload["rule"]["name"], json!("Greenhouse frost"));
+    }
+}
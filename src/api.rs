@@ -1,75 +1,300 @@
+use crate::aggregation::{AggregateFilter, AggregateRow};
+use crate::authentication::api_key::{ApiKey, Scope};
+use crate::device::SensorType;
 use crate::error::{AppError, AppResult};
+use crate::export::{csv_response, ndjson_response, Columns, ResponseFormat};
+use crate::feed::{CommittedRecord, FeedFilter};
+use crate::pagination::{Cursor, Page};
+use crate::repository::{self, Repository};
 use crate::state::AppState;
-use axum::extract::{FromRequestParts, Path, Query, State};
+use crate::temperature::{DecodedRecord, Units, UnitsQuery};
+use axum::extract::{FromRequestParts, Path, State};
 use axum::http::request::Parts;
 use axum::http::StatusCode;
-use axum::response::IntoResponse;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
 use axum::routing::{get, put};
 use axum::{Json, Router};
+use axum_extra::extract::Query;
+use futures_util::{Stream, TryStreamExt};
 use rerec::record::Record;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use sqlx::types::chrono::{DateTime, Utc};
+use std::convert::Infallible;
+use tokio::sync::broadcast::error::RecvError;
 use uuid::Uuid;
 
+mod alerts;
+mod devices;
+mod websocket;
+
 pub(crate) fn api() -> Router<AppState> {
     Router::new()
         .route("/records", get(get_records_by_filter))
         .route("/records", put(put_record))
+        .route("/records/batch", put(put_records))
+        .route("/records/latest", get(get_latest_records))
+        .route("/records/stream", get(stream_records))
+        .route("/records/ws", get(websocket::subscribe_records))
         .route("/records/bme280", get(get_bme280))
+        .route("/records/bme280/aggregate", get(get_bme280_aggregate))
         .route("/records/ds18b20", get(get_ds18b20))
+        .route("/records/ds18b20/aggregate", get(get_ds18b20_aggregate))
         .route("/records/{record_id}", get(get_record_by_id))
+        .route("/devices", get(devices::list_devices).post(devices::create_device))
+        .route("/devices/health", get(devices::device_health))
+        .route(
+            "/devices/{device_id}",
+            get(devices::get_device).put(devices::update_device).delete(devices::delete_device),
+        )
+        .route(
+            "/devices/{device_id}/calibrations",
+            get(devices::list_calibrations).post(devices::create_calibration),
+        )
+        .route(
+            "/devices/{device_id}/calibrations/{calibration_id}",
+            put(devices::update_calibration).delete(devices::delete_calibration),
+        )
+        .route("/alerts/rules", get(alerts::list_alert_rules).post(alerts::create_alert_rule))
+        .route(
+            "/alerts/rules/{rule_id}",
+            get(alerts::get_alert_rule).put(alerts::update_alert_rule).delete(alerts::delete_alert_rule),
+        )
+        .route("/alerts/events", get(alerts::list_alert_events))
 }
 
 async fn get_record_by_id(
     auth_token: AuthTokenValue,
     State(state): State<AppState>,
-    Path(record_id): Path<Uuid>
+    Path(record_id): Path<Uuid>,
+    Query(UnitsQuery { units }): Query<UnitsQuery>,
 ) -> AppResult<impl IntoResponse> {
-    auth_token.validate(&state).await?;
+    let api_key = auth_token.validate(&state, Scope::RecordsRead).await?;
 
-    let record = state.repository.get_record_by_id(record_id).await?;
+    let record = state.repository.get_record_by_id(record_id, api_key.owner_id()).await?;
 
     match record {
-        Some(record) => Ok((StatusCode::OK, Json(json!({"record": record})))),
+        Some(record) => {
+            let calibrations = state.repository.get_record_calibrations(std::slice::from_ref(&record)).await?;
+            let record = DecodedRecord::new(record, &units).calibrate(&calibrations);
+            Ok((StatusCode::OK, Json(json!({"record": record}))))
+        },
         None => Err(AppError::NotFound("No record with the supplied id was found"))
     }
 }
 
 async fn get_records_by_filter(
     auth_token: AuthTokenValue,
+    format: ResponseFormat,
     Query(filter): Query<RecordFilter>,
+    Query(UnitsQuery { units }): Query<UnitsQuery>,
+    State(state): State<AppState>,
+) -> AppResult<Response> {
+    let viewer_id = auth_token.validate(&state, Scope::RecordsRead).await?.owner_id();
+    filter.check_since_id(&state.repository, None, viewer_id).await?;
+
+    match format {
+        ResponseFormat::Ndjson => {
+            let records = state.repository.stream_records_by_filter(filter, viewer_id);
+            Ok(ndjson_response(decode_stream(state.repository, records, units)))
+        }
+        ResponseFormat::Csv => {
+            let records = state.repository.stream_records_by_filter(filter, viewer_id);
+            Ok(csv_response(records, Columns::All, "records.csv"))
+        }
+        ResponseFormat::Json => {
+            let page = state.repository.get_record_by_filter(&filter, viewer_id).await?;
+            let page = decode_page(&state, page, &units).await?;
+            Ok((StatusCode::OK, Json(page)).into_response())
+        }
+    }
+}
+
+async fn get_latest_records(
+    auth_token: AuthTokenValue,
+    Query(UnitsQuery { units }): Query<UnitsQuery>,
     State(state): State<AppState>,
 ) -> AppResult<impl IntoResponse> {
-    auth_token.validate(&state).await?;
+    let viewer_id = auth_token.validate(&state, Scope::RecordsRead).await?.owner_id();
 
-    let records = state.repository.get_record_by_filter(&filter).await?;
+    let records = state.repository.get_latest_records(viewer_id).await?;
+    let calibrations = state.repository.get_record_calibrations(&records).await?;
+    let records: Vec<DecodedRecord> = records
+        .into_iter()
+        .map(|record| DecodedRecord::new(record, &units).calibrate(&calibrations))
+        .collect();
 
     Ok((StatusCode::OK, Json(json!({"records": records}))))
 }
 
+/// Pushes every newly committed record matching the filter as a server-sent event.
+///
+/// Only records the caller may read are pushed. The API key is checked again periodically, and
+/// the stream ends once it is no longer accepted.
+async fn stream_records(
+    auth_token: AuthTokenValue,
+    Query(filter): Query<FeedFilter>,
+    Query(UnitsQuery { units }): Query<UnitsQuery>,
+    State(state): State<AppState>,
+) -> AppResult<impl IntoResponse> {
+    let mut access = FeedAccess::new(auth_token, &state).await?;
+
+    let mut receiver = state.record_feed.subscribe();
+    let events = async_stream::stream! {
+        loop {
+            let received = tokio::select! {
+                received = receiver.recv() => received,
+                _ = access.check_due() => {
+                    if let Err(error) = access.check(&state).await {
+                        tracing::debug!("Ending record stream, access check failed: {error:?}");
+                        break;
+                    }
+                    continue;
+                }
+            };
+            match received {
+                Ok(CommittedRecord { owner_id, record }) if access.may_read(owner_id) && filter.matches(&record) => {
+                    let id = record.id().to_string();
+                    let record = DecodedRecord::new(record, &units);
+                    match Event::default().event("record").id(id).json_data(record) {
+                        Ok(event) => yield Ok::<_, Infallible>(event),
+                        Err(error) => tracing::warn!("Unable to serialize record event: {error}"),
+                    }
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Record stream fell behind, skipped {skipped} records");
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    };
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
 async fn get_bme280(
     auth_token: AuthTokenValue,
+    format: ResponseFormat,
     Query(filter): Query<RecordFilter>,
     State(state): State<AppState>,
-) -> AppResult<impl IntoResponse> {
-    auth_token.validate(&state).await?;
+) -> AppResult<Response> {
+    let viewer_id = auth_token.validate(&state, Scope::RecordsRead).await?.owner_id();
 
-    let records = state.repository.get_bme280_by_filter(&filter).await?;
+    if !filter.device_name.is_empty() {
+        return Err(AppError::BadRequest("BME280 records cannot be filtered by device name"));
+    }
+    filter.check_since_id(&state.repository, Some(SensorType::Bme280), viewer_id).await?;
 
-    Ok((StatusCode::OK, Json(json!({"records": records}))))
+    match format {
+        ResponseFormat::Ndjson => {
+            let records = state.repository.stream_bme280_by_filter(filter, viewer_id);
+            Ok(ndjson_response(decode_stream(state.repository, records, Units::default())))
+        }
+        ResponseFormat::Csv => {
+            let records = state.repository.stream_bme280_by_filter(filter, viewer_id);
+            Ok(csv_response(records, Columns::Bme280, "bme280.csv"))
+        }
+        ResponseFormat::Json => {
+            let page = state.repository.get_bme280_by_filter(&filter, viewer_id).await?;
+            let page = decode_page(&state, page, &Units::default()).await?;
+            Ok((StatusCode::OK, Json(page)).into_response())
+        }
+    }
 }
 
 async fn get_ds18b20(
     auth_token: AuthTokenValue,
+    format: ResponseFormat,
     Query(filter): Query<RecordFilter>,
+    Query(UnitsQuery { units }): Query<UnitsQuery>,
     State(state): State<AppState>
+) -> AppResult<Response> {
+    let viewer_id = auth_token.validate(&state, Scope::RecordsRead).await?.owner_id();
+    filter.check_since_id(&state.repository, Some(SensorType::Ds18b20), viewer_id).await?;
+
+    match format {
+        ResponseFormat::Ndjson => {
+            let records = state.repository.stream_ds18b20_by_filter(filter, viewer_id);
+            Ok(ndjson_response(decode_stream(state.repository, records, units)))
+        }
+        ResponseFormat::Csv => {
+            let records = state.repository.stream_ds18b20_by_filter(filter, viewer_id);
+            Ok(csv_response(records, Columns::Ds18b20, "ds18b20.csv"))
+        }
+        ResponseFormat::Json => {
+            let page = state.repository.get_ds18b20_by_filter(&filter, viewer_id).await?;
+            let page = decode_page(&state, page, &units).await?;
+            Ok((StatusCode::OK, Json(page)).into_response())
+        }
+    }
+}
+
+/// Decodes the records of a page and adds their calibrated values.
+async fn decode_page(state: &AppState, page: Page, units: &Units) -> AppResult<Page<DecodedRecord>> {
+    let calibrations = state.repository.get_record_calibrations(&page.records).await?;
+    Ok(page.map(|record| DecodedRecord::new(record, units).calibrate(&calibrations)))
+}
+
+/// Decodes streamed records and adds their calibrated values. The calibrations are looked up
+/// for a chunk of records at a time.
+fn decode_stream(
+    repository: Repository,
+    records: impl Stream<Item = Result<Record, sqlx::Error>> + Send + 'static,
+    units: Units,
+) -> impl Stream<Item = Result<DecodedRecord, sqlx::Error>> + Send + 'static {
+    async_stream::try_stream! {
+        let mut chunks = std::pin::pin!(records.try_chunks(CALIBRATION_CHUNK_SIZE));
+        while let Some(chunk) = chunks.try_next().await.map_err(|error| error.1)? {
+            let calibrations = repository.get_record_calibrations(&chunk).await?;
+            for record in chunk {
+                yield DecodedRecord::new(record, &units).calibrate(&calibrations);
+            }
+        }
+    }
+}
+
+async fn get_bme280_aggregate(
+    auth_token: AuthTokenValue,
+    Query(filter): Query<AggregateFilter>,
+    State(state): State<AppState>,
 ) -> AppResult<impl IntoResponse> {
-    auth_token.validate(&state).await?;
+    let viewer_id = auth_token.validate(&state, Scope::RecordsRead).await?.owner_id();
+
+    if !filter.device_name.is_empty() {
+        return Err(AppError::BadRequest("BME280 records cannot be filtered by device name"));
+    }
+    filter.validate().map_err(|error| AppError::BadRequest(error.message()))?;
 
-    let records = state.repository.get_ds18b20_by_filter(&filter).await?;
+    let aggregates: Vec<AggregateRow> = state
+        .repository
+        .get_bme280_aggregate(&filter, viewer_id)
+        .await?
+        .into_iter()
+        .map(|row| row.select(&filter.functions))
+        .collect();
 
-    Ok((StatusCode::OK, Json(json!({"records": records}))))
+    Ok((StatusCode::OK, Json(json!({"aggregates": aggregates}))))
+}
+
+async fn get_ds18b20_aggregate(
+    auth_token: AuthTokenValue,
+    Query(filter): Query<AggregateFilter>,
+    State(state): State<AppState>,
+) -> AppResult<impl IntoResponse> {
+    let viewer_id = auth_token.validate(&state, Scope::RecordsRead).await?.owner_id();
+    filter.validate().map_err(|error| AppError::BadRequest(error.message()))?;
+
+    let aggregates: Vec<AggregateRow> = state
+        .repository
+        .get_ds18b20_aggregate(&filter, viewer_id)
+        .await?
+        .into_iter()
+        .map(|row| row.select(&filter.functions))
+        .collect();
+
+    Ok((StatusCode::OK, Json(json!({"aggregates": aggregates}))))
 }
 
 async fn put_record(
@@ -77,21 +302,154 @@ async fn put_record(
     State(state): State<AppState>,
     Json(record): Json<Record>,
 ) -> AppResult<impl IntoResponse> {
-    auth_token.validate(&state).await?;
+    let api_key = auth_token.validate(&state, Scope::RecordsWrite).await?;
 
-    let record_id = state
-        .repository
-        .commit_record(record)
-        .await
-        .map_err(AppError::from_commit_record_error)?;
+    let outcome = state.repository.commit_record(&record, &api_key).await;
 
-    Ok((
-        StatusCode::CREATED,
-        Json(json!({
-            "message": "record saved successfully",
-            "record_id": record_id
-        }))
-    ))
+    match RecordCommitResult::new(&state, &record, outcome, &api_key).await? {
+        RecordCommitResult::Created { record_id } => {
+            state.webhook_dispatcher.enqueue(api_key.owner_id(), std::slice::from_ref(&record));
+            state.alert_evaluator.enqueue(std::slice::from_ref(&record), api_key.owner_id(), api_key.id());
+            Ok((
+                StatusCode::CREATED,
+                Json(json!({
+                    "message": "record saved successfully",
+                    "record_id": record_id
+                }))
+            ))
+        }
+        RecordCommitResult::Duplicate { record_id } => Ok((
+            StatusCode::OK,
+            Json(json!({
+                "message": "record already saved",
+                "record_id": record_id
+            }))
+        )),
+        RecordCommitResult::Conflict { .. } => Err(AppError::Conflict(ID_CONFLICT_MESSAGE)),
+        RecordCommitResult::Invalid { reason, .. } => Err(AppError::BadRequest(reason)),
+    }
+}
+
+async fn put_records(
+    auth_token: AuthTokenValue,
+    State(state): State<AppState>,
+    Json(records): Json<Vec<Record>>,
+) -> AppResult<impl IntoResponse> {
+    let api_key = auth_token.validate(&state, Scope::RecordsWrite).await?;
+
+    let outcomes = state.repository.commit_records(&records, &api_key).await?;
+
+    let mut results = Vec::with_capacity(records.len());
+    let mut created = Vec::new();
+    for (record, outcome) in records.iter().zip(outcomes) {
+        let result = RecordCommitResult::new(&state, record, outcome, &api_key).await?;
+        if let RecordCommitResult::Created { .. } = result {
+            created.push(record.clone());
+        }
+        results.push(result);
+    }
+
+    state.webhook_dispatcher.enqueue(api_key.owner_id(), &created);
+    state.alert_evaluator.enqueue(&created, api_key.owner_id(), api_key.id());
+
+    Ok((StatusCode::OK, Json(json!({"results": results}))))
+}
+
+/// How many streamed records to look up calibrations for at once.
+const CALIBRATION_CHUNK_SIZE: usize = 500;
+/// How often the access of a live feed subscriber is checked again.
+const FEED_ACCESS_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+const ID_CONFLICT_MESSAGE: &str = "a record with the same id but different content already exists";
+
+/// The outcome of committing a single record, either on its own or as part of a batch.
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum RecordCommitResult {
+    Created { record_id: Uuid },
+    /// The record was already stored with identical content, i.e. a harmless replay.
+    Duplicate { record_id: Uuid },
+    /// The record id is already in use by a record with different content.
+    Conflict { record_id: Uuid, reason: &'static str },
+    Invalid { record_id: Uuid, reason: &'static str },
+}
+
+impl RecordCommitResult {
+    /// The outcome of committing a record with the API key. A record with the same id owned by
+    /// a user the owner of the key may not read is reported as a conflict, without comparing it.
+    async fn new(
+        state: &AppState,
+        record: &Record,
+        outcome: Result<Uuid, sqlx::Error>,
+        api_key: &ApiKey,
+    ) -> AppResult<Self> {
+        let record_id = record.id();
+        match outcome {
+            Ok(record_id) => Ok(RecordCommitResult::Created { record_id }),
+            Err(error) => match AppError::from_commit_record_error(error) {
+                AppError::Conflict(_) => {
+                    let stored = state.repository.get_record_by_id(record_id, api_key.owner_id()).await?;
+                    if stored.is_some_and(|stored| is_same_record(&stored, record)) {
+                        Ok(RecordCommitResult::Duplicate { record_id })
+                    } else {
+                        Ok(RecordCommitResult::Conflict { record_id, reason: ID_CONFLICT_MESSAGE })
+                    }
+                }
+                AppError::BadRequest(reason) => Ok(RecordCommitResult::Invalid { record_id, reason }),
+                error => Err(error),
+            },
+        }
+    }
+}
+
+/// Whether two records have identical content.
+///
+/// Timestamps are compared at microsecond precision, as that is the precision in which the
+/// database stores them.
+fn is_same_record(stored: &Record, incoming: &Record) -> bool {
+    stored.id() == incoming.id()
+        && stored.timestamp().timestamp_micros() == incoming.timestamp().timestamp_micros()
+        && stored.reading() == incoming.reading()
+}
+
+/// The access of a live feed subscriber to the records of the feed.
+///
+/// A subscription lasts as long as its connection, so the API key is checked again every
+/// [`FEED_ACCESS_CHECK_INTERVAL`], along with whose records it may read. Revoking the key,
+/// letting it expire, disabling its owner or withdrawing a share thus takes effect on open
+/// subscriptions too.
+struct FeedAccess {
+    auth_token: AuthTokenValue,
+    owners: Vec<Uuid>,
+    checks: tokio::time::Interval,
+}
+
+impl FeedAccess {
+    async fn new(auth_token: AuthTokenValue, state: &AppState) -> AppResult<Self> {
+        let owners = auth_token.visible_owners(state).await?;
+        let start = tokio::time::Instant::now() + FEED_ACCESS_CHECK_INTERVAL;
+        let mut checks = tokio::time::interval_at(start, FEED_ACCESS_CHECK_INTERVAL);
+        checks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        Ok(Self { auth_token, owners, checks })
+    }
+
+    /// Whether the subscriber may read the records of the owner.
+    fn may_read(&self, owner_id: Uuid) -> bool {
+        self.owners.contains(&owner_id)
+    }
+
+    /// Completes when the access is due to be checked again. Safe to cancel, e.g. in a
+    /// `select!` on the feed.
+    async fn check_due(&mut self) {
+        self.checks.tick().await;
+    }
+
+    /// Checks the API key again and updates whose records it may read. An error means the
+    /// subscription must end.
+    async fn check(&mut self, state: &AppState) -> AppResult<()> {
+        self.owners = self.auth_token.visible_owners(state).await?;
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -104,16 +462,44 @@ impl AuthTokenValue {
         Self { value: token }
     }
 
-    pub async fn validate(&self, state: &AppState) -> Result<(), AppError> {
-        match state
+    /// Looks up the API key of the token, rejecting unknown tokens and keys without the scope
+    /// the route requires.
+    pub async fn validate(&self, state: &AppState, scope: Scope) -> Result<ApiKey, AppError> {
+        let api_key = match state
             .repository
-            .get_api_key_by_token(&self.value)
+            .get_api_key_by_token_hash(&state.token_hasher.hash(&self.value))
             .await
         {
-            Ok(_) => Ok(()),
-            Err(sqlx::Error::RowNotFound) => Err(AppError::Unauthorized("unknown API key token")),
-            Err(error) => Err(AppError::SqlxError(error)),
+            Ok(api_key) => api_key,
+            Err(sqlx::Error::RowNotFound) => return Err(AppError::Unauthorized("unknown API key token")),
+            Err(error) => return Err(AppError::SqlxError(error)),
+        };
+
+        if let Some(message) = api_key.status(Utc::now()).rejection_message() {
+            return Err(AppError::Unauthorized(message));
         }
+
+        // The owner is checked on every request, so disabling an account or making its user a
+        // viewer takes effect on the keys at once.
+        let owner = state.repository.get_user_by_id(api_key.owner_id()).await?;
+        let Some(owner) = owner.filter(|owner| !owner.disabled()) else {
+            return Err(AppError::Unauthorized("API key owner's account is disabled"));
+        };
+        if scope.writes() && !owner.role().can_write() {
+            return Err(AppError::Forbidden("API key owner is a viewer and cannot make changes"));
+        }
+
+        if !api_key.has_scope(scope) {
+            return Err(AppError::Forbidden(scope.missing_message()));
+        }
+
+        Ok(api_key)
+    }
+
+    /// Validates the token for reading records and returns the users whose records it may read.
+    async fn visible_owners(&self, state: &AppState) -> AppResult<Vec<Uuid>> {
+        let api_key = self.validate(state, Scope::RecordsRead).await?;
+        Ok(state.repository.list_visible_owners(api_key.owner_id()).await?)
     }
 }
 
@@ -158,5 +544,145 @@ impl<S: Send + Sync> FromRequestParts<S> for AuthTokenValue {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct RecordFilter {
     pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
     pub limit: Option<u32>,
+    pub cursor: Option<Cursor>,
+    #[serde(default)]
+    pub order: Order,
+    /// Only include DS18B20 records from these devices. The parameter may be repeated.
+    #[serde(default)]
+    pub device_name: Vec<String>,
+    /// Only include records after the record with this id in the requested order.
+    pub since_id: Option<Uuid>,
+}
+
+impl RecordFilter {
+    /// Limits the filter to the default page size, unless it has a limit of its own or a time
+    /// range bounded on both ends. Streamed records are otherwise unlimited, which suits a client
+    /// asking for a complete export, but not a casual click on a download link.
+    pub fn with_default_limit(self) -> Self {
+        let bounded = self.from.is_some() && self.to.is_some();
+        let limit = self.limit.or((!bounded).then_some(repository::DEFAULT_LIMIT));
+        Self { limit, ..self }
+    }
+
+    /// Checks that the `since_id`, if given, is the id of a record the viewer may read, of the
+    /// given sensor type if any. Otherwise, no record would ever follow it, and a client polling
+    /// for newer records could not tell that from there being none.
+    pub async fn check_since_id(
+        &self,
+        repository: &Repository,
+        sensor: Option<SensorType>,
+        viewer_id: Uuid,
+    ) -> AppResult<()> {
+        let Some(since_id) = self.since_id else {
+            return Ok(());
+        };
+        let record = match sensor {
+            None => repository.get_record_by_id(since_id, viewer_id).await?,
+            Some(SensorType::Bme280) => repository.get_bme280_record_by_id(since_id, viewer_id).await?,
+            Some(SensorType::Ds18b20) => repository.get_ds18b20_record_by_id(since_id, viewer_id).await?,
+        };
+        match record {
+            Some(_) => Ok(()),
+            None => Err(AppError::NotFound("since_id does not refer to a record you may read")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Order {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl Order {
+    pub fn as_sql(&self) -> &'static str {
+        match self {
+            Order::Asc => "ASC",
+            Order::Desc => "DESC",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rerec::Reading;
+    use rerec::ds18b20::DS18B20;
+
+    fn ds18b20_record(timestamp: DateTime<Utc>, raw_reading: i32) -> Record {
+        let id = Uuid::parse_str("7e9b1a33-05fb-48e3-86b6-21ddc873c06f").unwrap();
+        let reading = Reading::DS18B20(DS18B20::new("0000003e33d5".to_string(), raw_reading));
+        Record::new(id, timestamp, reading)
+    }
+
+    fn filter(from: Option<i64>, to: Option<i64>, limit: Option<u32>) -> RecordFilter {
+        RecordFilter {
+            from: from.and_then(|from| DateTime::from_timestamp(from, 0)),
+            to: to.and_then(|to| DateTime::from_timestamp(to, 0)),
+            limit,
+            cursor: None,
+            order: Order::Asc,
+            device_name: Vec::new(),
+            since_id: None,
+        }
+    }
+
+    #[test]
+    fn test_with_default_limit() {
+        assert_eq!(filter(None, None, None).with_default_limit().limit, Some(repository::DEFAULT_LIMIT));
+        assert_eq!(filter(Some(0), None, None).with_default_limit().limit, Some(repository::DEFAULT_LIMIT));
+        assert_eq!(filter(None, None, Some(20000)).with_default_limit().limit, Some(20000));
+        assert_eq!(filter(Some(0), Some(3600), None).with_default_limit().limit, None);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a database at DATABASE_URL"]
+    async fn test_concurrent_exports_share_a_small_pool() {
+        use crate::repository::tests::{ds18b20, new_api_key, new_user, repository};
+
+        // Fewer connections than exports, each export longer than a chunk of calibrations.
+        let repository = repository(2).await;
+        let user = new_user(&repository).await;
+        let api_key = new_api_key(&repository, &user, "greenhouse").await;
+        let records: Vec<Record> = (0..1200).map(|seconds| ds18b20(seconds, "0000003e33d5")).collect();
+        repository.commit_records(&records, &api_key).await.unwrap();
+
+        let exports = (0..6).map(|_| {
+            let records = repository.stream_ds18b20_by_filter(filter(None, None, None), *user.id());
+            decode_stream(repository.clone(), records, Units::default()).try_collect::<Vec<_>>()
+        });
+        let exported = tokio::time::timeout(
+            std::time::Duration::from_secs(30),
+            futures_util::future::join_all(exports),
+        )
+        .await;
+
+        repository.delete_user(*user.id()).await.unwrap();
+        for export in exported.expect("the exports did not finish") {
+            assert_eq!(export.unwrap().len(), records.len());
+        }
+    }
+
+    #[test]
+    fn test_is_same_record() {
+        let timestamp = DateTime::parse_from_rfc3339("2026-02-27T09:32:45.123456Z").unwrap().to_utc();
+        let stored = ds18b20_record(timestamp, 22123);
+
+        assert!(is_same_record(&stored, &ds18b20_record(timestamp, 22123)));
+        assert!(!is_same_record(&stored, &ds18b20_record(timestamp, 22124)));
+    }
+
+    #[test]
+    fn test_is_same_record_ignores_sub_microsecond_precision() {
+        let stored = DateTime::parse_from_rfc3339("2026-02-27T09:32:45.123456Z").unwrap().to_utc();
+        let incoming = DateTime::parse_from_rfc3339("2026-02-27T09:32:45.123456789Z").unwrap().to_utc();
+        let later = DateTime::parse_from_rfc3339("2026-02-27T09:32:45.123457Z").unwrap().to_utc();
+
+        assert!(is_same_record(&ds18b20_record(stored, 22123), &ds18b20_record(incoming, 22123)));
+        assert!(!is_same_record(&ds18b20_record(stored, 22123), &ds18b20_record(later, 22123)));
+    }
 }
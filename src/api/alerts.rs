@@ -0,0 +1,120 @@
+use super::AuthTokenValue;
+use crate::authentication::api_key::Scope;
+use crate::alert::{AlertRule, AlertRuleDetails};
+use crate::error::{AppError, AppResult};
+use crate::state::AppState;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use axum_extra::extract::Query;
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+const DEFAULT_HISTORY_LIMIT: u32 = 100;
+const HARD_HISTORY_LIMIT: u32 = 1000;
+
+pub(super) async fn list_alert_rules(
+    auth_token: AuthTokenValue,
+    State(state): State<AppState>,
+) -> AppResult<impl IntoResponse> {
+    let owner_id = auth_token.validate(&state, Scope::RecordsRead).await?.owner_id();
+
+    let rules = state.repository.list_alert_rules(owner_id).await?;
+
+    Ok((StatusCode::OK, Json(json!({"rules": rules}))))
+}
+
+pub(super) async fn get_alert_rule(
+    auth_token: AuthTokenValue,
+    State(state): State<AppState>,
+    Path(rule_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    let owner_id = auth_token.validate(&state, Scope::RecordsRead).await?.owner_id();
+
+    let rule = state
+        .repository
+        .get_alert_rule_by_id(rule_id, owner_id)
+        .await?
+        .ok_or(AppError::NotFound(NOT_FOUND_MESSAGE))?;
+
+    Ok((StatusCode::OK, Json(json!({"rule": rule}))))
+}
+
+pub(super) async fn create_alert_rule(
+    auth_token: AuthTokenValue,
+    State(state): State<AppState>,
+    Json(details): Json<AlertRuleDetails>,
+) -> AppResult<impl IntoResponse> {
+    let owner_id = auth_token.validate(&state, Scope::DevicesWrite).await?.owner_id();
+    validate(&state, &details, owner_id).await?;
+
+    let rule = AlertRule::new(details);
+    state.repository.create_alert_rule(owner_id, &rule).await?;
+
+    Ok((StatusCode::CREATED, Json(json!({"rule": rule}))))
+}
+
+pub(super) async fn update_alert_rule(
+    auth_token: AuthTokenValue,
+    State(state): State<AppState>,
+    Path(rule_id): Path<Uuid>,
+    Json(details): Json<AlertRuleDetails>,
+) -> AppResult<impl IntoResponse> {
+    let owner_id = auth_token.validate(&state, Scope::DevicesWrite).await?.owner_id();
+    validate(&state, &details, owner_id).await?;
+
+    let rule = AlertRule { id: rule_id, ..AlertRule::new(details) };
+    if !state.repository.update_alert_rule(owner_id, &rule).await? {
+        return Err(AppError::NotFound(NOT_FOUND_MESSAGE));
+    }
+
+    Ok((StatusCode::OK, Json(json!({"rule": rule}))))
+}
+
+pub(super) async fn delete_alert_rule(
+    auth_token: AuthTokenValue,
+    State(state): State<AppState>,
+    Path(rule_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    let owner_id = auth_token.validate(&state, Scope::DevicesWrite).await?.owner_id();
+
+    if !state.repository.delete_alert_rule(owner_id, rule_id).await? {
+        return Err(AppError::NotFound(NOT_FOUND_MESSAGE));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct HistoryQuery {
+    limit: Option<u32>,
+}
+
+/// The latest times alerts fired or resolved, newest first.
+pub(super) async fn list_alert_events(
+    auth_token: AuthTokenValue,
+    State(state): State<AppState>,
+    Query(HistoryQuery { limit }): Query<HistoryQuery>,
+) -> AppResult<impl IntoResponse> {
+    let owner_id = auth_token.validate(&state, Scope::RecordsRead).await?.owner_id();
+
+    let limit = limit.unwrap_or(DEFAULT_HISTORY_LIMIT).min(HARD_HISTORY_LIMIT);
+    let events = state.repository.list_alert_history(owner_id, limit).await?;
+
+    Ok((StatusCode::OK, Json(json!({"events": events}))))
+}
+
+/// Checks the rule against the sensor type of its device, which must be a device of the owner.
+async fn validate(state: &AppState, details: &AlertRuleDetails, owner_id: Uuid) -> AppResult<()> {
+    let device = state
+        .repository
+        .get_owned_device(owner_id, details.device_id)
+        .await?
+        .ok_or(AppError::BadRequest("device_id does not refer to a device of yours"))?;
+
+    details.validate(device.details.sensor).map_err(AppError::BadRequest)
+}
+
+const NOT_FOUND_MESSAGE: &str = "No alert rule with the supplied id was found";
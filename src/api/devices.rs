@@ -0,0 +1,198 @@
+use super::AuthTokenValue;
+use crate::authentication::api_key::Scope;
+use crate::calibration::{Calibration, CalibrationDetails};
+use crate::device::{Device, DeviceDetails, HealthQuery};
+use crate::error::{AppError, AppResult};
+use crate::state::AppState;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use axum_extra::extract::Query;
+use serde_json::json;
+use sqlx::types::chrono::Utc;
+use uuid::Uuid;
+
+pub(super) async fn list_devices(
+    auth_token: AuthTokenValue,
+    State(state): State<AppState>,
+) -> AppResult<impl IntoResponse> {
+    let viewer_id = auth_token.validate(&state, Scope::RecordsRead).await?.owner_id();
+
+    let devices = state.repository.list_devices(viewer_id).await?;
+
+    Ok((StatusCode::OK, Json(json!({"devices": devices}))))
+}
+
+/// When each DS18B20 probe and BME280 station was last seen, and whether it has been silent for
+/// longer than the expected interval.
+pub(super) async fn device_health(
+    auth_token: AuthTokenValue,
+    State(state): State<AppState>,
+    Query(HealthQuery { expected_interval }): Query<HealthQuery>,
+) -> AppResult<impl IntoResponse> {
+    let viewer_id = auth_token.validate(&state, Scope::RecordsRead).await?.owner_id();
+
+    let now = Utc::now();
+    let devices: Vec<_> = state
+        .repository
+        .list_device_health(viewer_id)
+        .await?
+        .into_iter()
+        .map(|health| health.check(now, expected_interval))
+        .collect();
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({"expected_interval_seconds": expected_interval.seconds(), "devices": devices})),
+    ))
+}
+
+pub(super) async fn get_device(
+    auth_token: AuthTokenValue,
+    State(state): State<AppState>,
+    Path(device_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    let viewer_id = auth_token.validate(&state, Scope::RecordsRead).await?.owner_id();
+
+    let device = find_device(&state, device_id, viewer_id).await?;
+
+    Ok((StatusCode::OK, Json(json!({"device": device}))))
+}
+
+pub(super) async fn create_device(
+    auth_token: AuthTokenValue,
+    State(state): State<AppState>,
+    Json(details): Json<DeviceDetails>,
+) -> AppResult<impl IntoResponse> {
+    let owner_id = auth_token.validate(&state, Scope::DevicesWrite).await?.owner_id();
+    details.validate().map_err(AppError::BadRequest)?;
+
+    let device = Device::new(details);
+    state
+        .repository
+        .create_device(owner_id, &device)
+        .await
+        .map_err(AppError::from_commit_device_error)?;
+
+    Ok((StatusCode::CREATED, Json(json!({"device": device}))))
+}
+
+pub(super) async fn update_device(
+    auth_token: AuthTokenValue,
+    State(state): State<AppState>,
+    Path(device_id): Path<Uuid>,
+    Json(details): Json<DeviceDetails>,
+) -> AppResult<impl IntoResponse> {
+    let owner_id = auth_token.validate(&state, Scope::DevicesWrite).await?.owner_id();
+    details.validate().map_err(AppError::BadRequest)?;
+
+    let device = Device { id: device_id, details };
+    let updated = state
+        .repository
+        .update_device(owner_id, &device)
+        .await
+        .map_err(AppError::from_commit_device_error)?;
+
+    if !updated {
+        return Err(AppError::NotFound(NOT_FOUND_MESSAGE));
+    }
+
+    Ok((StatusCode::OK, Json(json!({"device": device}))))
+}
+
+pub(super) async fn delete_device(
+    auth_token: AuthTokenValue,
+    State(state): State<AppState>,
+    Path(device_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    let owner_id = auth_token.validate(&state, Scope::DevicesWrite).await?.owner_id();
+
+    if !state.repository.delete_device(owner_id, device_id).await? {
+        return Err(AppError::NotFound(NOT_FOUND_MESSAGE));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(super) async fn list_calibrations(
+    auth_token: AuthTokenValue,
+    State(state): State<AppState>,
+    Path(device_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    let viewer_id = auth_token.validate(&state, Scope::RecordsRead).await?.owner_id();
+    find_device(&state, device_id, viewer_id).await?;
+
+    let calibrations = state.repository.list_calibrations(device_id).await?;
+
+    Ok((StatusCode::OK, Json(json!({"calibrations": calibrations}))))
+}
+
+pub(super) async fn create_calibration(
+    auth_token: AuthTokenValue,
+    State(state): State<AppState>,
+    Path(device_id): Path<Uuid>,
+    Json(details): Json<CalibrationDetails>,
+) -> AppResult<impl IntoResponse> {
+    let owner_id = auth_token.validate(&state, Scope::DevicesWrite).await?.owner_id();
+    let device = find_owned_device(&state, device_id, owner_id).await?;
+    details.validate(device.details.sensor).map_err(AppError::BadRequest)?;
+
+    let calibration = Calibration::new(device_id, details);
+    state.repository.create_calibration(&calibration).await?;
+
+    Ok((StatusCode::CREATED, Json(json!({"calibration": calibration}))))
+}
+
+pub(super) async fn update_calibration(
+    auth_token: AuthTokenValue,
+    State(state): State<AppState>,
+    Path((device_id, calibration_id)): Path<(Uuid, Uuid)>,
+    Json(details): Json<CalibrationDetails>,
+) -> AppResult<impl IntoResponse> {
+    let owner_id = auth_token.validate(&state, Scope::DevicesWrite).await?.owner_id();
+    let device = find_owned_device(&state, device_id, owner_id).await?;
+    details.validate(device.details.sensor).map_err(AppError::BadRequest)?;
+
+    let calibration = Calibration { id: calibration_id, device_id, details };
+    if !state.repository.update_calibration(owner_id, &calibration).await? {
+        return Err(AppError::NotFound(CALIBRATION_NOT_FOUND_MESSAGE));
+    }
+
+    Ok((StatusCode::OK, Json(json!({"calibration": calibration}))))
+}
+
+pub(super) async fn delete_calibration(
+    auth_token: AuthTokenValue,
+    State(state): State<AppState>,
+    Path((device_id, calibration_id)): Path<(Uuid, Uuid)>,
+) -> AppResult<impl IntoResponse> {
+    let owner_id = auth_token.validate(&state, Scope::DevicesWrite).await?.owner_id();
+
+    if !state.repository.delete_calibration(owner_id, device_id, calibration_id).await? {
+        return Err(AppError::NotFound(CALIBRATION_NOT_FOUND_MESSAGE));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The device, if the viewer may read the records of its owner.
+async fn find_device(state: &AppState, device_id: Uuid, viewer_id: Uuid) -> AppResult<Device> {
+    state
+        .repository
+        .get_device_by_id(device_id, viewer_id)
+        .await?
+        .ok_or(AppError::NotFound(NOT_FOUND_MESSAGE))
+}
+
+/// The device, if it belongs to the owner. Devices shared with the owner cannot be changed.
+async fn find_owned_device(state: &AppState, device_id: Uuid, owner_id: Uuid) -> AppResult<Device> {
+    state
+        .repository
+        .get_owned_device(owner_id, device_id)
+        .await?
+        .ok_or(AppError::NotFound(NOT_FOUND_MESSAGE))
+}
+
+const NOT_FOUND_MESSAGE: &str = "No device with the supplied id was found";
+const CALIBRATION_NOT_FOUND_MESSAGE: &str = "No calibration with the supplied id was found for the device";
@@ -0,0 +1,195 @@
+use super::{AuthTokenValue, FeedAccess};
+use crate::device::SensorType;
+use crate::error::AppResult;
+use crate::feed::CommittedRecord;
+use crate::state::AppState;
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::response::IntoResponse;
+use rerec::Reading;
+use rerec::record::Record;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Upgrades to a WebSocket on which newly committed records are sent to the client.
+///
+/// The client is authenticated by its API key before the upgrade. Nothing is sent until the
+/// client subscribes to a sensor type or a device, and only records the client may read are
+/// sent. The API key is checked again periodically, and the socket is closed once it is no
+/// longer accepted.
+pub(super) async fn subscribe_records(
+    auth_token: AuthTokenValue,
+    State(state): State<AppState>,
+    upgrade: WebSocketUpgrade,
+) -> AppResult<impl IntoResponse> {
+    let access = FeedAccess::new(auth_token, &state).await?;
+
+    let records = state.record_feed.subscribe();
+    Ok(upgrade.on_upgrade(move |socket| serve(socket, records, access, state)))
+}
+
+async fn serve(
+    mut socket: WebSocket,
+    mut records: broadcast::Receiver<CommittedRecord>,
+    mut access: FeedAccess,
+    state: AppState,
+) {
+    let mut subscriptions = Subscriptions::default();
+
+    loop {
+        let reply = tokio::select! {
+            _ = access.check_due() => {
+                if let Err(error) = access.check(&state).await {
+                    tracing::debug!("Closing WebSocket, access check failed: {error:?}");
+                    let frame = CloseFrame { code: close_code::POLICY, reason: "API key no longer accepted".into() };
+                    _ = socket.send(Message::Close(Some(frame))).await;
+                    break;
+                }
+                continue;
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => subscriptions.handle(&text),
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                // Pings are answered by the socket itself.
+                Some(Ok(_)) => continue,
+            },
+            record = records.recv() => match record {
+                Ok(CommittedRecord { owner_id, record }) if access.may_read(owner_id) && subscriptions.matches(&record) => {
+                    serde_json::to_string(&record)
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("WebSocket subscriber fell behind, skipped {skipped} records");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+        };
+
+        let reply = match reply {
+            Ok(reply) => reply,
+            Err(error) => {
+                tracing::warn!("Unable to serialize WebSocket message: {error}");
+                continue;
+            }
+        };
+
+        if socket.send(Message::Text(reply.into())).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// A message from the client, changing what it is subscribed to.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    Subscribe(Topics),
+    Unsubscribe(Topics),
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Topics {
+    #[serde(default)]
+    sensors: Vec<SensorType>,
+    #[serde(default)]
+    device_names: Vec<String>,
+}
+
+/// A message to the client other than a record. Records are sent as they are, so these are
+/// told apart from records by their `type`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage<'a> {
+    /// Everything the client is subscribed to, sent in reply to every change.
+    Subscriptions(&'a Subscriptions),
+    Error { message: String },
+}
+
+/// The sensor types and DS18B20 devices a client is subscribed to.
+///
+/// A record is sent if the client is subscribed to either its sensor type or its device.
+#[derive(Debug, Default, Serialize)]
+struct Subscriptions {
+    sensors: BTreeSet<SensorType>,
+    device_names: BTreeSet<String>,
+}
+
+impl Subscriptions {
+    /// Applies a message from the client and returns the reply.
+    fn handle(&mut self, text: &str) -> serde_json::Result<String> {
+        match serde_json::from_str::<ClientMessage>(text) {
+            Ok(ClientMessage::Subscribe(topics)) => {
+                self.sensors.extend(topics.sensors);
+                self.device_names.extend(topics.device_names);
+            }
+            Ok(ClientMessage::Unsubscribe(topics)) => {
+                for sensor in &topics.sensors {
+                    self.sensors.remove(sensor);
+                }
+                for device_name in &topics.device_names {
+                    self.device_names.remove(device_name);
+                }
+            }
+            Err(error) => {
+                let message = format!("invalid message: {error}");
+                return serde_json::to_string(&ServerMessage::Error { message });
+            }
+        }
+
+        serde_json::to_string(&ServerMessage::Subscriptions(self))
+    }
+
+    fn matches(&self, record: &Record) -> bool {
+        let device_subscribed = match record.reading() {
+            Reading::DS18B20(reading) => self.device_names.contains(reading.device_name()),
+            Reading::BME280(_) => false,
+        };
+        device_subscribed || self.sensors.contains(&SensorType::of(record))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rerec::bme280::BME280;
+    use rerec::ds18b20::DS18B20;
+    use serde_json::{json, Value};
+    use sqlx::types::chrono::Utc;
+    use uuid::Uuid;
+
+    fn ds18b20(device_name: &str) -> Record {
+        Record::new(Uuid::new_v4(), Utc::now(), Reading::DS18B20(DS18B20::new(device_name.to_string(), 22123)))
+    }
+
+    fn handle(subscriptions: &mut Subscriptions, message: Value) -> Value {
+        let reply = subscriptions.handle(&message.to_string()).unwrap();
+        serde_json::from_str(&reply).unwrap()
+    }
+
+    #[test]
+    fn test_subscribe_and_unsubscribe() {
+        let mut subscriptions = Subscriptions::default();
+        let bme280 = Record::new(Uuid::new_v4(), Utc::now(), Reading::BME280(BME280::new(21.5, 100000.0, 45.0)));
+        assert!(!subscriptions.matches(&bme280));
+
+        let reply = handle(&mut subscriptions, json!({"type": "subscribe", "sensors": ["bme280"], "device_names": ["0000003e33d5"]}));
+        assert_eq!(reply, json!({"type": "subscriptions", "sensors": ["bme280"], "device_names": ["0000003e33d5"]}));
+        assert!(subscriptions.matches(&bme280));
+        assert!(subscriptions.matches(&ds18b20("0000003e33d5")));
+        assert!(!subscriptions.matches(&ds18b20("000000a1b2c3")));
+
+        let reply = handle(&mut subscriptions, json!({"type": "unsubscribe", "sensors": ["bme280"]}));
+        assert_eq!(reply, json!({"type": "subscriptions", "sensors": [], "device_names": ["0000003e33d5"]}));
+        assert!(!subscriptions.matches(&bme280));
+    }
+
+    #[test]
+    fn test_invalid_message() {
+        let mut subscriptions = Subscriptions::default();
+        let reply = handle(&mut subscriptions, json!({"type": "subscribe", "sensors": ["dht22"]}));
+        assert_eq!(reply["type"], "error");
+    }
+}
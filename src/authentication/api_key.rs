@@ -1,11 +1,13 @@
+use crate::error::{AppError, AppResult};
 use crate::state::AppState;
-use crate::web::TERA;
-use axum::extract::State;
+use crate::web::{parse_utc, TERA};
+use axum::extract::{Path, State};
 use axum::http::{header, HeaderValue, StatusCode};
-use axum::response::{Html, IntoResponse};
-use axum::routing::post;
+use axum::response::{Html, IntoResponse, Redirect};
+use axum::routing::{delete, post, put};
 use axum::{Form, Router};
 use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
 use uuid::Uuid;
 use crate::authentication::token::Token;
 use crate::authentication::user_auth::AuthUser;
@@ -13,6 +15,11 @@ use crate::authentication::user_auth::AuthUser;
 pub(crate) fn router() -> Router<AppState> {
     Router::new()
         .route("/", post(create_api_key))
+        .route("/{api_key_id}", delete(delete_api_key))
+        .route("/{api_key_id}/revoked", put(revoke_api_key))
+        // HTML forms can only POST.
+        .route("/{api_key_id}/revoke", post(revoke_api_key_form))
+        .route("/{api_key_id}/delete", post(delete_api_key_form))
 }
 
 async fn create_api_key(
@@ -24,12 +31,29 @@ async fn create_api_key(
         tracing::warn!("REJECTED token creation attempt: owner does not match");
         return (StatusCode::FORBIDDEN, "Creation of keys owner by other users is not allowed.").into_response();
     }
-    let key = ApiKey::from(api_key_form_data);
-    match state.repository.create_api_key(&key).await {
+    let scopes = api_key_form_data.scopes();
+    if scopes.is_empty() {
+        return (StatusCode::BAD_REQUEST, "An API key must have at least one scope.").into_response();
+    }
+    if !user.role().can_write() && scopes.iter().any(Scope::writes) {
+        return (StatusCode::FORBIDDEN, "Viewers may only create keys with the records:read scope.").into_response();
+    }
+    let expires_at = match api_key_form_data.expires_at() {
+        Ok(expires_at) => expires_at,
+        Err(error) => return error.into_response(),
+    };
+    if expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+        return (StatusCode::BAD_REQUEST, "An API key must not expire in the past.").into_response();
+    }
+    let (key, token) = ApiKey::new(&api_key_form_data.name, user.id(), user.username(), scopes, expires_at);
+    let token_hash = state.token_hasher.hash(&token.to_string());
+    match state.repository.create_api_key(&key, &token_hash).await {
         Ok(_) => {
+            // The token is only stored as a hash, so this is the only time it can be shown.
             let mut context = tera::Context::new();
             context.insert("username", user.username());
             context.insert("key", &key);
+            context.insert("token", &token.to_string());
             let output = TERA.render("api_key.html", &context).unwrap();
 
             let mut res = (StatusCode::CREATED, Html(output)).into_response();
@@ -41,6 +65,9 @@ async fn create_api_key(
 
             res
         }
+        Err(sqlx::Error::Database(error)) if error.is_unique_violation() => {
+            (StatusCode::CONFLICT, "You already have an API key with this name.").into_response()
+        }
         Err(error) => {
             tracing::error!("Failed to create token: {}", error);
             (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error: Could not create token!").into_response()
@@ -48,33 +75,153 @@ async fn create_api_key(
     }
 }
 
+/// Revokes an API key of the user. A revoked key is rejected, but kept and listed until it is
+/// deleted. Revoking a revoked key again succeeds without changing it.
+async fn revoke_api_key(
+    user: AuthUser,
+    State(state): State<AppState>,
+    Path(api_key_id): Path<Uuid>,
+) -> AppResult<StatusCode> {
+    if !state.repository.revoke_api_key(user.id(), api_key_id).await? {
+        return Err(AppError::NotFound(NOT_FOUND_MESSAGE));
+    }
+    tracing::info!("API key {api_key_id} revoked by {}", user.username());
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn delete_api_key(
+    user: AuthUser,
+    State(state): State<AppState>,
+    Path(api_key_id): Path<Uuid>,
+) -> AppResult<StatusCode> {
+    if !state.repository.delete_api_key(user.id(), api_key_id).await? {
+        return Err(AppError::NotFound(NOT_FOUND_MESSAGE));
+    }
+    tracing::info!("API key {api_key_id} deleted by {}", user.username());
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn revoke_api_key_form(
+    user: AuthUser,
+    state: State<AppState>,
+    api_key_id: Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    revoke_api_key(user, state, api_key_id).await?;
+    Ok(Redirect::to("/api_keys"))
+}
+
+async fn delete_api_key_form(
+    user: AuthUser,
+    state: State<AppState>,
+    api_key_id: Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    delete_api_key(user, state, api_key_id).await?;
+    Ok(Redirect::to("/api_keys"))
+}
+
+const NOT_FOUND_MESSAGE: &str = "no API key with this id is owned by you";
+
+/// The API key form, where a scope is granted by checking its box, and an empty expiry means
+/// the key does not expire.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct ApiKeyFormData {
     name: String,
     owner: String,
+    #[serde(default)]
+    expires_at: String,
+    #[serde(default)]
+    records_read: bool,
+    #[serde(default)]
+    records_write: bool,
+    #[serde(default)]
+    devices_write: bool,
+}
+
+impl ApiKeyFormData {
+    fn expires_at(&self) -> AppResult<Option<DateTime<Utc>>> {
+        match self.expires_at.trim() {
+            "" => Ok(None),
+            expires_at => parse_utc(expires_at).map(Some),
+        }
+    }
+
+    fn scopes(&self) -> Vec<Scope> {
+        [
+            (self.records_read, Scope::RecordsRead),
+            (self.records_write, Scope::RecordsWrite),
+            (self.devices_write, Scope::DevicesWrite),
+        ]
+        .into_iter()
+        .filter_map(|(granted, scope)| granted.then_some(scope))
+        .collect()
+    }
+}
+
+/// What an API key is allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "text")]
+pub(crate) enum Scope {
+    /// Read records, devices and alerts, including the live feeds.
+    #[serde(rename = "records:read")]
+    #[sqlx(rename = "records:read")]
+    RecordsRead,
+    /// Submit records.
+    #[serde(rename = "records:write")]
+    #[sqlx(rename = "records:write")]
+    RecordsWrite,
+    /// Register and change devices, their calibrations and alert rules.
+    #[serde(rename = "devices:write")]
+    #[sqlx(rename = "devices:write")]
+    DevicesWrite,
 }
 
-impl From<ApiKeyFormData> for ApiKey {
-    fn from(form_data: ApiKeyFormData) -> Self {
-        ApiKey::new(&form_data.name, &form_data.owner)
+impl Scope {
+    /// The error message for a request made with an API key lacking the scope.
+    pub fn missing_message(&self) -> &'static str {
+        match self {
+            Scope::RecordsRead => "API key does not have the records:read scope",
+            Scope::RecordsWrite => "API key does not have the records:write scope",
+            Scope::DevicesWrite => "API key does not have the devices:write scope",
+        }
+    }
+
+    /// Whether the scope allows changing anything, which viewers may not.
+    pub fn writes(&self) -> bool {
+        *self != Scope::RecordsRead
     }
 }
 
+/// An API key, without its token. Only a hash of the token is stored, along with the first
+/// few characters of it, telling keys apart without revealing them.
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub(crate) struct ApiKey {
     id: Uuid,
     name: String,
+    owner_id: Uuid,
     owner: String,
-    token: String,
+    token_prefix: String,
+    scopes: Vec<Scope>,
+    /// When the key stops being accepted, if ever.
+    expires_at: Option<DateTime<Utc>>,
+    /// When the key was revoked, if it has been.
+    revoked_at: Option<DateTime<Utc>>,
 }
 
 impl ApiKey {
-    pub fn new(name: &str, owner: &str) -> Self {
+    /// A new API key along with its token.
+    pub fn new(
+        name: &str,
+        owner_id: Uuid,
+        owner: &str,
+        scopes: Vec<Scope>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> (Self, Token) {
         let id = Uuid::new_v4();
         let name = name.to_string();
         let owner = owner.to_string();
-        let token = Token::new().to_string();
-        Self { id, name, owner, token }
+        let token = Token::new();
+        let token_prefix = token.prefix().to_string();
+        (Self { id, name, owner_id, owner, token_prefix, scopes, expires_at, revoked_at: None }, token)
     }
 
     pub fn id(&self) -> Uuid {
@@ -85,11 +232,116 @@ impl ApiKey {
         &self.name
     }
 
-    pub fn owner(&self) -> &str {
-        &self.owner
+    /// The user owning the key, who also owns the records submitted with it.
+    pub fn owner_id(&self) -> Uuid {
+        self.owner_id
     }
 
-    pub fn token(&self) -> String {
-        self.token.to_string()
+    pub fn token_prefix(&self) -> &str {
+        &self.token_prefix
     }
-}
\ No newline at end of file
+
+    pub fn scopes(&self) -> &[Scope] {
+        &self.scopes
+    }
+
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        self.expires_at
+    }
+
+    pub fn status(&self, now: DateTime<Utc>) -> ApiKeyStatus {
+        if self.revoked_at.is_some() {
+            ApiKeyStatus::Revoked
+        } else if self.expires_at.is_some_and(|expires_at| expires_at <= now) {
+            ApiKeyStatus::Expired
+        } else {
+            ApiKeyStatus::Active
+        }
+    }
+}
+
+/// Whether an API key is accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ApiKeyStatus {
+    Active,
+    Revoked,
+    Expired,
+}
+
+impl ApiKeyStatus {
+    /// The error message for a request made with a key that is not accepted.
+    pub fn rejection_message(&self) -> Option<&'static str> {
+        match self {
+            ApiKeyStatus::Active => None,
+            ApiKeyStatus::Revoked => Some("API key has been revoked"),
+            ApiKeyStatus::Expired => Some("API key has expired"),
+        }
+    }
+}
+
+/// An API key as listed on the API keys page.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ApiKeyListing {
+    #[serde(flatten)]
+    pub key: ApiKey,
+    pub status: ApiKeyStatus,
+}
+
+impl ApiKeyListing {
+    pub fn new(key: ApiKey, now: DateTime<Utc>) -> Self {
+        let status = key.status(now);
+        Self { key, status }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scopes_from_form() {
+        let form_data = ApiKeyFormData {
+            name: "greenhouse".to_string(),
+            owner: "tester".to_string(),
+            expires_at: String::new(),
+            records_read: false,
+            records_write: true,
+            devices_write: false,
+        };
+        let (key, token) = ApiKey::new(&form_data.name, Uuid::new_v4(), &form_data.owner, form_data.scopes(), None);
+        assert_eq!(key.scopes(), [Scope::RecordsWrite]);
+        assert!(token.to_string().starts_with(key.token_prefix()));
+        assert!(key.has_scope(Scope::RecordsWrite));
+        assert!(!key.has_scope(Scope::RecordsRead));
+    }
+
+    #[test]
+    fn test_status() {
+        let now = Utc::now();
+        let expires_at = DateTime::from_timestamp(1772184765, 0).unwrap();
+        let (key, _) = ApiKey::new("greenhouse", Uuid::new_v4(), "tester", vec![Scope::RecordsWrite], Some(expires_at));
+        assert_eq!(key.status(DateTime::from_timestamp(1772184764, 0).unwrap()), ApiKeyStatus::Active);
+        assert_eq!(key.status(expires_at), ApiKeyStatus::Expired);
+
+        let revoked = ApiKey { revoked_at: Some(now), ..key.clone() };
+        assert_eq!(revoked.status(expires_at), ApiKeyStatus::Revoked);
+
+        let unexpiring = ApiKey { expires_at: None, ..key };
+        assert_eq!(unexpiring.status(now), ApiKeyStatus::Active);
+        assert_eq!(ApiKeyStatus::Active.rejection_message(), None);
+        assert_ne!(ApiKeyStatus::Revoked.rejection_message(), ApiKeyStatus::Expired.rejection_message());
+    }
+
+    #[test]
+    fn test_scope_names() {
+        for scope in [Scope::RecordsRead, Scope::RecordsWrite, Scope::DevicesWrite] {
+            let name = serde_json::to_value(scope).unwrap();
+            assert!(scope.missing_message().ends_with(&format!("the {} scope", name.as_str().unwrap())));
+        }
+    }
+}
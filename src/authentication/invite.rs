@@ -0,0 +1,34 @@
+use crate::authentication::token::Token;
+use crate::authentication::user::Role;
+use serde::Serialize;
+use sqlx::types::chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// A single-use code created by an admin, letting someone register an account with the role
+/// of the invite. Like API key tokens, only a hash of the code is stored.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub(crate) struct Invite {
+    pub id: Uuid,
+    pub role: Role,
+    /// The admin who created the invite, unless their account has since been deleted.
+    pub created_by: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// The user who registered with the invite, if it has been used.
+    pub used_by: Option<String>,
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+impl Invite {
+    /// A new invite along with its code.
+    pub fn new(role: Role, created_by: &str) -> (Self, Token) {
+        let invite = Self {
+            id: Uuid::new_v4(),
+            role,
+            created_by: Some(created_by.to_string()),
+            created_at: Utc::now(),
+            used_by: None,
+            used_at: None,
+        };
+        (invite, Token::new())
+    }
+}
@@ -1,5 +1,9 @@
 pub mod api_key;
+pub mod invite;
+pub mod registration;
 pub mod token;
+pub mod totp;
+pub mod totp_api;
 pub mod user;
 pub mod user_api;
 pub mod user_auth;
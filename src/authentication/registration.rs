@@ -0,0 +1,43 @@
+use serde::Serialize;
+use std::str::FromStr;
+
+/// Who may register an account without being created by an admin. Regardless of the mode, a
+/// valid invite code creates a user with the role of the invite, and the very first user of an
+/// empty database becomes an admin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistrationMode {
+    /// Anyone may register as a member.
+    Open,
+    /// Only those with an invite code may register.
+    #[default]
+    Invite,
+    /// Anyone may register as a member, but cannot log in until an admin approves the account.
+    Approval,
+}
+
+impl FromStr for RegistrationMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "open" => Ok(RegistrationMode::Open),
+            "invite" => Ok(RegistrationMode::Invite),
+            "approval" => Ok(RegistrationMode::Approval),
+            other => Err(format!("unknown registration mode '{other}', expected open, invite or approval")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("open".parse(), Ok(RegistrationMode::Open));
+        assert_eq!(" Invite ".parse(), Ok(RegistrationMode::Invite));
+        assert_eq!("APPROVAL".parse(), Ok(RegistrationMode::Approval));
+        assert!("closed".parse::<RegistrationMode>().is_err());
+    }
+}
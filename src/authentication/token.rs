@@ -1,8 +1,13 @@
-use std::fmt::Display;
+use std::fmt::{Display, Write};
 use base64::Engine;
 use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+/// How many characters of a token are kept in the clear to tell tokens apart.
+const PREFIX_LENGTH: usize = 8;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct Token {
@@ -19,6 +24,12 @@ impl Token {
 
         Self { value: base64_encoded }
     }
+
+    /// The first few characters of the token, which identify it without revealing it.
+    pub fn prefix(&self) -> &str {
+        let end = self.value.char_indices().nth(PREFIX_LENGTH).map_or(self.value.len(), |(index, _)| index);
+        &self.value[..end]
+    }
 }
 
 impl Display for Token {
@@ -38,3 +49,57 @@ impl From<Token> for String {
         value.value
     }
 }
+
+/// Hashes tokens for storage.
+///
+/// The hash is an HMAC-SHA256 keyed with a server secret, so stored hashes are of no use without
+/// the secret, which is not kept in the database.
+#[derive(Clone)]
+pub(crate) struct TokenHasher {
+    secret: Vec<u8>,
+}
+
+impl TokenHasher {
+    pub fn new(secret: &str) -> Self {
+        Self { secret: secret.as_bytes().to_vec() }
+    }
+
+    pub fn hash(&self, token: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(token.as_bytes());
+        encode_hex(&mac.finalize().into_bytes())
+    }
+}
+
+/// Lowercase hexadecimal encoding.
+pub(crate) fn encode_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        _ = write!(hex, "{byte:02x}");
+    }
+    hex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefix() {
+        let token = Token::from("99ea32d6-e0dc-4b2c-9802-6eaeaf55bbac".to_string());
+        assert_eq!(token.prefix(), "99ea32d6");
+        assert_eq!(Token::from("abc".to_string()).prefix(), "abc");
+        assert_eq!(Token::new().prefix().len(), PREFIX_LENGTH);
+    }
+
+    #[test]
+    fn test_hash() {
+        // Test case 2 of RFC 4231.
+        let hasher = TokenHasher::new("Jefe");
+        assert_eq!(
+            hasher.hash("what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_ne!(TokenHasher::new("other").hash("what do ya want for nothing?"), hasher.hash("what do ya want for nothing?"));
+    }
+}
@@ -0,0 +1,140 @@
+use crate::authentication::token::encode_hex;
+use qrcode::QrCode;
+use qrcode::render::svg;
+use rand::Rng;
+use subtle::ConstantTimeEq;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+/// The issuer shown next to the account in authenticator apps.
+const ISSUER: &str = "Herodot";
+/// The number of recovery codes issued when enrolling.
+const RECOVERY_CODE_COUNT: usize = 10;
+/// How many time steps a code may be off, allowing for clock drift.
+const SKEW: u8 = 1;
+/// Seconds per time step, as RFC 6238 recommends.
+const STEP: u64 = 30;
+
+/// A time-based one-time password (RFC 6238) second factor, as understood by common
+/// authenticator apps: six digits, SHA-1 and 30 second time steps.
+pub(crate) struct SecondFactor {
+    totp: TOTP,
+}
+
+impl SecondFactor {
+    /// A second factor with a new random secret.
+    pub fn generate(username: &str) -> Self {
+        let mut secret = [0u8; 20];
+        rand::rng().fill_bytes(&mut secret);
+        Self::with_secret(secret.to_vec(), username)
+    }
+
+    /// The second factor with the base32 encoded secret.
+    pub fn from_secret(secret: &str, username: &str) -> Option<Self> {
+        let secret = Secret::Encoded(secret.to_string()).to_bytes().ok()?;
+        Some(Self::with_secret(secret, username))
+    }
+
+    fn with_secret(secret: Vec<u8>, username: &str) -> Self {
+        // Unchecked, as the username is URL encoded in the otpauth URL, colons and all.
+        let totp = TOTP::new_unchecked(
+            Algorithm::SHA1,
+            6,
+            SKEW,
+            STEP,
+            secret,
+            Some(ISSUER.to_string()),
+            username.to_string(),
+        );
+        Self { totp }
+    }
+
+    /// The base32 encoded secret, for storage and for entering into an app by hand.
+    pub fn secret(&self) -> String {
+        self.totp.get_secret_base32()
+    }
+
+    /// The `otpauth://` URL of the second factor, which apps read from a QR code.
+    pub fn url(&self) -> String {
+        self.totp.get_url()
+    }
+
+    /// The `otpauth://` URL as a QR code in SVG.
+    pub fn qr_code_svg(&self) -> String {
+        QrCode::new(self.url())
+            .expect("an otpauth URL fits in a QR code")
+            .render::<svg::Color>()
+            .min_dimensions(200, 200)
+            .build()
+    }
+
+    /// The time step of the code, if it is valid at `time`, in seconds since the Unix epoch.
+    /// Codes of the neighbouring time steps are accepted as well, allowing for clock drift.
+    ///
+    /// Codes are compared in constant time, so the time taken does not tell how much of a code
+    /// is right.
+    pub fn verify(&self, code: &str, time: u64) -> Option<u64> {
+        let code = code.trim().as_bytes();
+        let current = time / STEP;
+        (current.saturating_sub(SKEW as u64)..=current + SKEW as u64)
+            .find(|step| bool::from(self.totp.generate(step * STEP).as_bytes().ct_eq(code)))
+    }
+}
+
+/// New recovery codes, each usable once in place of a TOTP code.
+pub(crate) fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::rng();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; 5];
+            rng.fill_bytes(&mut bytes);
+            let hex = encode_hex(&bytes);
+            format!("{}-{}", &hex[..5], &hex[5..])
+        })
+        .collect()
+}
+
+/// A recovery code as entered, without the separator, spaces or capitals, ready for hashing.
+pub(crate) fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The SHA-1 secret of the RFC 6238 test vectors, "12345678901234567890", in base32.
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn test_verify() {
+        let second_factor = SecondFactor::from_secret(RFC_SECRET, "tester").unwrap();
+
+        // RFC 6238 appendix B, truncated to six digits.
+        assert_eq!(second_factor.verify("287082", 59), Some(1));
+        assert_eq!(second_factor.verify("081804", 1111111109), Some(37037036));
+        // A code of the previous time step is still accepted, but not one from long ago.
+        assert_eq!(second_factor.verify("081804", 1111111109 + STEP), Some(37037036));
+        assert_eq!(second_factor.verify("081804", 1111111109 + 3 * STEP), None);
+        assert_eq!(second_factor.verify("000000", 59), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let second_factor = SecondFactor::generate("a:b");
+        let restored = SecondFactor::from_secret(&second_factor.secret(), "a:b").unwrap();
+        assert_eq!(restored.secret(), second_factor.secret());
+        assert!(second_factor.url().starts_with("otpauth://totp/Herodot:a%3Ab?secret="));
+        assert!(second_factor.qr_code_svg().contains("<svg"));
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert!(codes.iter().all(|code| code.len() == 11 && code.as_bytes()[5] == b'-'));
+        assert_eq!(normalize_recovery_code(" AB12C-3d4e5 "), "ab12c3d4e5");
+    }
+}
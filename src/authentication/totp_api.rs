@@ -0,0 +1,106 @@
+use crate::authentication::totp::{generate_recovery_codes, normalize_recovery_code, SecondFactor};
+use crate::authentication::user_auth::AuthUser;
+use crate::error::{AppError, AppResult};
+use crate::state::AppState;
+use crate::web::TERA;
+use axum::extract::State;
+use axum::http::{header, HeaderValue};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::routing::post;
+use axum::{Form, Router};
+use serde::Deserialize;
+use sqlx::types::chrono::Utc;
+use tower_sessions::Session;
+
+/// The session key of the secret shown while enrolling, until the user confirms it with a code.
+const SETUP_SECRET_KEY: &str = "totp_setup_secret";
+
+#[derive(Debug, Clone, Deserialize)]
+struct TotpCodeFormData {
+    code: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TotpDisableFormData {
+    password: String,
+}
+
+pub(crate) fn router() -> Router<AppState> {
+    Router::new()
+        .route("/setup", post(setup))
+        .route("/enable", post(enable))
+        .route("/disable", post(disable))
+}
+
+/// Starts enrolling a second factor by showing a new secret as a QR code. The second factor is
+/// not enabled until the user enters a code from their app.
+async fn setup(user: AuthUser, session: Session) -> AppResult<Response> {
+    let second_factor = SecondFactor::generate(user.username());
+    session.insert(SETUP_SECRET_KEY, second_factor.secret()).await?;
+
+    let mut context = tera::Context::new();
+    context.insert("username", user.username());
+    context.insert("qr_code", &second_factor.qr_code_svg());
+    context.insert("secret", &second_factor.secret());
+    let output = TERA.render("totp_setup.html", &context)?;
+    Ok(no_store(Html(output)))
+}
+
+/// Enables the second factor being enrolled, if the code matches, and shows new recovery codes.
+async fn enable(
+    user: AuthUser,
+    session: Session,
+    State(state): State<AppState>,
+    Form(form_data): Form<TotpCodeFormData>,
+) -> AppResult<Response> {
+    let Some(secret) = session.get::<String>(SETUP_SECRET_KEY).await? else {
+        return Err(AppError::BadRequest("Bad Request: Start setting up two-factor authentication first!"));
+    };
+    let second_factor = SecondFactor::from_secret(&secret, user.username())
+        .ok_or(AppError::InternalServerError("invalid TOTP secret"))?;
+    if second_factor.verify(&form_data.code, Utc::now().timestamp() as u64).is_none() {
+        return Err(AppError::BadRequest("Bad Request: The code is wrong, check the clock of your device!"));
+    }
+
+    let recovery_codes = generate_recovery_codes();
+    let recovery_code_hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|code| state.token_hasher.hash(&normalize_recovery_code(code)))
+        .collect();
+    state.repository.enable_totp(user.id(), &secret, &recovery_code_hashes).await?;
+    session.remove::<String>(SETUP_SECRET_KEY).await?;
+    tracing::info!("two-factor authentication enabled, username: {}", user.username());
+
+    // Only hashes of the recovery codes are stored, so this is the only time they can be shown.
+    let mut context = tera::Context::new();
+    context.insert("username", user.username());
+    context.insert("recovery_codes", &recovery_codes);
+    let output = TERA.render("totp_recovery_codes.html", &context)?;
+    Ok(no_store(Html(output)))
+}
+
+async fn disable(
+    user: AuthUser,
+    State(state): State<AppState>,
+    Form(form_data): Form<TotpDisableFormData>,
+) -> AppResult<impl IntoResponse> {
+    let stored_user = state
+        .repository
+        .get_user_by_id(user.id())
+        .await?
+        .ok_or(AppError::Unauthorized("Unauthorized"))?;
+    if !stored_user.password_matches(&form_data.password)? {
+        tracing::debug!("REJECTED disabling two-factor authentication: wrong password, username: {}", user.username());
+        return Err(AppError::Forbidden("Forbidden: The password is wrong!"));
+    }
+
+    state.repository.disable_totp(user.id()).await?;
+    tracing::info!("two-factor authentication disabled, username: {}", user.username());
+    Ok(Redirect::to("/me"))
+}
+
+fn no_store(response: impl IntoResponse) -> Response {
+    let mut res = response.into_response();
+    res.headers_mut().insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    res
+}
@@ -5,6 +5,31 @@ use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use uuid::Uuid;
 
+/// What a user is allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub(crate) enum Role {
+    /// Manages users, invites and every API key, in addition to what a member may do.
+    Admin,
+    /// Reads records and manages their own devices, alerts, webhooks, shares and API keys.
+    #[default]
+    Member,
+    /// Only reads records, and may only have API keys with the records:read scope.
+    Viewer,
+}
+
+impl Role {
+    pub fn is_admin(&self) -> bool {
+        *self == Role::Admin
+    }
+
+    /// Whether the role may change anything besides its own API keys.
+    pub fn can_write(&self) -> bool {
+        *self != Role::Viewer
+    }
+}
+
 /// User model representing a user account in the system
 /// This model is mapped to the database table `auth.users` and contains user credentials.
 /// The database is unaware of password hashing, and as such knows only the field for hashed
@@ -15,6 +40,14 @@ pub(crate) struct User {
     username: String,
     #[sqlx(rename = "password")]
     hashed_password: String,
+    #[serde(default)]
+    role: Role,
+    /// A disabled user can neither log in nor use their API keys.
+    #[serde(default)]
+    disabled: bool,
+    /// A user who registered while approval is required cannot log in until an admin approves.
+    #[serde(default)]
+    pending: bool,
 }
 
 impl User {
@@ -22,9 +55,14 @@ impl User {
         &self.id
     }
 
-    pub(crate) fn new(username: String, password: String) -> Result<Self, UserError> {
+    pub(crate) fn new(username: String, password: String, role: Role) -> Result<Self, UserError> {
         let hashed_password = hash_password(password)?;
-        Ok(Self { id: Uuid::new_v4(), username, hashed_password })
+        Ok(Self { id: Uuid::new_v4(), username, hashed_password, role, disabled: false, pending: false })
+    }
+
+    /// The user, awaiting approval by an admin.
+    pub(crate) fn into_pending(self) -> Self {
+        Self { pending: true, ..self }
     }
 
     pub(crate) fn credentials_is(&self, credentials: UserCredentials) -> Result<bool, UserError> {
@@ -37,7 +75,7 @@ impl User {
         }
     }
 
-    fn password_matches(&self, password: &str) -> Result<bool, UserError> {
+    pub(crate) fn password_matches(&self, password: &str) -> Result<bool, UserError> {
         let argon2 = Argon2::default();
         let parsed_password_hash = PasswordHash::new(&self.hashed_password)?;
         Ok(argon2.verify_password(password.as_bytes(), &parsed_password_hash).is_ok())
@@ -50,6 +88,28 @@ impl User {
     pub fn hashed_password(&self) -> &str {
         &self.hashed_password
     }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn disabled(&self) -> bool {
+        self.disabled
+    }
+
+    pub fn pending(&self) -> bool {
+        self.pending
+    }
+}
+
+/// A user as listed on the admin pages, without the password.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub(crate) struct UserSummary {
+    pub id: Uuid,
+    pub username: String,
+    pub role: Role,
+    pub disabled: bool,
+    pub pending: bool,
 }
 
 pub(crate) struct UserCredentials {
@@ -84,7 +144,7 @@ impl From<argon2::password_hash::Error> for UserError {
     }
 }
 
-fn hash_password(password: String) -> Result<String, UserError> {
+pub(crate) fn hash_password(password: String) -> Result<String, UserError> {
     let salt = SaltString::generate(&mut OsRng);
     let argon2 = Argon2::default();
     match argon2.hash_password(password.as_bytes(), &salt) {
@@ -101,22 +161,30 @@ mod tests {
     fn test_hash_password() {
         let password = "securepassword123";
         let hashed_password = hash_password(password.to_string()).unwrap();
-        assert!(hashed_password.len() > 0);
+        assert!(!hashed_password.is_empty());
     }
 
     #[test]
     fn test_password_matches() {
         let password = "securepassword123";
         let wrong_password = "not the password";
-        let user = User::new("testuser".to_string(), password.to_string()).unwrap();
+        let user = User::new("testuser".to_string(), password.to_string(), Role::Member).unwrap();
         assert!(user.password_matches(password).unwrap());
         assert!(!user.password_matches(wrong_password).unwrap());
     }
 
     #[test]
     fn test_credentials_is() {
-        let user = User::new("testuser".to_string(), "securepassword123".to_string()).unwrap();
+        let user = User::new("testuser".to_string(), "securepassword123".to_string(), Role::Member).unwrap();
         let credentials = UserCredentials::new("testuser".to_string(), "wrongpassword".to_string());
         assert!(!user.credentials_is(credentials).unwrap());
     }
+
+    #[test]
+    fn test_roles() {
+        assert!(Role::Admin.is_admin() && Role::Admin.can_write());
+        assert!(!Role::Member.is_admin() && Role::Member.can_write());
+        assert!(!Role::Viewer.is_admin() && !Role::Viewer.can_write());
+        assert_eq!(serde_json::to_value(Role::Viewer).unwrap(), "viewer");
+    }
 }
\ No newline at end of file
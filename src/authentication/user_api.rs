@@ -1,12 +1,19 @@
 use crate::state::AppState;
-use crate::authentication::user::UserCredentials;
+use crate::authentication::registration::RegistrationMode;
+use crate::authentication::totp::{normalize_recovery_code, SecondFactor};
+use crate::authentication::totp_api;
+use crate::authentication::user::{hash_password, Role, UserCredentials};
+use crate::authentication::user_auth::AuthUser;
 use axum::extract::State;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Redirect};
 use axum::routing::{get, post};
 use axum::{Form, Router};
 use serde::Deserialize;
+use sqlx::types::chrono::Utc;
+use std::time::Duration;
 use tower_sessions::Session;
+use uuid::Uuid;
 use crate::error::{AppError, AppResult};
 
 #[derive(Debug, Clone, Deserialize)]
@@ -26,17 +33,65 @@ struct UserCreationFormData {
     pub(crate) username: String,
     pub(crate) password: String,
     #[serde(rename = "password-confirm")]
-    pub(crate) password_confirmation: String
+    pub(crate) password_confirmation: String,
+    #[serde(default, rename = "invite-code")]
+    pub(crate) invite_code: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TotpLoginFormData {
+    code: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct PasswordChangeFormData {
+    #[serde(rename = "current-password")]
+    current_password: String,
+    password: String,
+    #[serde(rename = "password-confirm")]
+    password_confirmation: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct PasswordResetFormData {
+    token: String,
+    password: String,
+    #[serde(rename = "password-confirm")]
+    password_confirmation: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct AccountDeletionFormData {
+    password: String,
 }
 
 pub(crate) fn user_router() -> Router<AppState> {
     Router::new()
         .route("/register", post(register))
         .route("/login", post(login))
+        .route("/login/totp", post(login_totp))
         .route("/logout", get(logout))
+        .route("/password", post(change_password))
+        .route("/reset_password", post(reset_password))
+        .route("/delete", post(delete_account))
+        .nest("/totp", totp_api::router())
 }
 
+/// The session key of the user who has entered their password, but not yet a TOTP code.
+const TOTP_PENDING_USER_KEY: &str = "totp_pending_user";
+/// The number of TOTP codes which may be entered without one being accepted, after which the
+/// second factor of a user is locked.
+const MAX_TOTP_ATTEMPTS: i32 = 5;
+/// How long the second factor stays locked. No code is accepted meanwhile, so that codes cannot
+/// be guessed by logging in again and again.
+const TOTP_LOCKOUT: Duration = Duration::from_secs(15 * 60);
+
+/// Creates a user, if requested by an admin, with a valid invite code, if there are no users
+/// yet, or as allowed by the registration mode. An admin creates members, an invite creates a
+/// user with the role of the invite and the very first user becomes an admin. Otherwise, the
+/// user is a member, who must be approved by an admin before logging in if the mode requires it.
 async fn register(
+    user: Result<AuthUser, AppError>,
     State(state): State<AppState>,
     Form(credentials): Form<UserCreationFormData>
 ) -> AppResult<impl IntoResponse> {
@@ -45,10 +100,46 @@ async fn register(
         return Err(AppError::BadRequest("Bad Request: Password confirmation must match password!"));
     }
 
-    match state.repository.create_user(&credentials.username, &credentials.password).await {
-        Ok(_user) => {
+    let username = &credentials.username;
+    let password = &credentials.password;
+    let invite_code = credentials.invite_code.trim();
+    let created = if user.is_ok_and(|user| user.role().is_admin()) {
+        state.repository.create_user(username, password, Role::Member).await.map(Some)
+    } else if !invite_code.is_empty() {
+        let code_hash = state.token_hasher.hash(invite_code);
+        state.repository.create_user_with_invite(username, password, &code_hash).await
+    } else {
+        match state.repository.create_first_user(username, password).await {
+            Ok(None) => match state.registration_mode {
+                RegistrationMode::Open => state.repository.create_user(username, password, Role::Member).await.map(Some),
+                RegistrationMode::Approval => state.repository.create_pending_user(username, password).await.map(Some),
+                RegistrationMode::Invite => Ok(None),
+            },
+            first_user => first_user,
+        }
+    };
+
+    match created {
+        Ok(Some(user)) if user.pending() => {
+            tracing::info!("user created pending approval, username: {}", user.username());
+            Ok((StatusCode::ACCEPTED, "User created, and awaiting approval by an admin"))
+        }
+        Ok(Some(user)) => {
+            tracing::info!("user created, username: {}, role: {:?}", user.username(), user.role());
             Ok((StatusCode::CREATED, "User created successfully"))
         }
+        Ok(None) if invite_code.is_empty() => {
+            tracing::debug!("REJECTED user creation attempt: no invite code");
+            Err(AppError::Forbidden("Forbidden: An invite code is required to register!"))
+        }
+        Ok(None) => {
+            tracing::debug!("REJECTED user creation attempt: unknown or used invite code");
+            Err(AppError::Forbidden("Forbidden: The invite code is not valid!"))
+        }
+        Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {
+            tracing::debug!("REJECTED user creation attempt: username {} is taken", username);
+            Err(AppError::Conflict("Conflict: The username is taken!"))
+        }
         Err(err) => {
             tracing::error!("REJECTED user creation attempt: {}", err);
             Err(AppError::InternalServerError("Internal Server Error: Could not create user!"))
@@ -67,6 +158,20 @@ async fn login(
         Ok(user) => {
             let credentials: UserCredentials = credentials.into();
             if user.credentials_is(credentials)? {
+                if user.disabled() {
+                    tracing::debug!("User login rejected, account disabled, username: {}", username);
+                    return Ok(Redirect::to("/login?error=account_disabled"));
+                }
+                if user.pending() {
+                    tracing::debug!("User login rejected, account pending approval, username: {}", username);
+                    return Ok(Redirect::to("/login?error=account_pending"));
+                }
+                if state.repository.get_totp_secret(*user.id()).await?.is_some() {
+                    tracing::debug!("user entered password, awaiting TOTP code, username: {}", username);
+                    session.insert(TOTP_PENDING_USER_KEY, *user.id()).await?;
+                    return Ok(Redirect::to("/login/totp"));
+                }
+
                 match session.insert("user", user.clone()).await {
                     Ok(_) => {
                         tracing::debug!("user logged in, username: {}", username);
@@ -90,6 +195,131 @@ async fn login(
     }
 }
 
+/// The second login step of users with two-factor authentication, taking either a TOTP code or
+/// a recovery code. The user is only logged in once this step succeeds.
+async fn login_totp(
+    session: Session,
+    State(state): State<AppState>,
+    Form(form_data): Form<TotpLoginFormData>
+) -> AppResult<impl IntoResponse> {
+    let Some(user_id) = session.get::<Uuid>(TOTP_PENDING_USER_KEY).await? else {
+        return Ok(Redirect::to("/login"));
+    };
+    let user = state.repository.get_user_by_id(user_id).await?.filter(|user| !user.disabled() && !user.pending());
+    let secret = state.repository.get_totp_secret(user_id).await?;
+    let (Some(user), Some(secret)) = (user, secret) else {
+        session.remove::<Uuid>(TOTP_PENDING_USER_KEY).await?;
+        return Ok(Redirect::to("/login"));
+    };
+
+    let attempt = state.repository
+        .reserve_totp_attempt(user_id, MAX_TOTP_ATTEMPTS, TOTP_LOCKOUT.as_secs_f64())
+        .await?;
+    let Some(attempt) = attempt else {
+        tracing::debug!("User login rejected, second factor locked, username: {}", user.username());
+        session.remove::<Uuid>(TOTP_PENDING_USER_KEY).await?;
+        return Ok(Redirect::to("/login?error=too_many_attempts"));
+    };
+
+    let second_factor = SecondFactor::from_secret(&secret, user.username())
+        .ok_or(AppError::InternalServerError("invalid TOTP secret"))?;
+    let accepted = match second_factor.verify(&form_data.code, Utc::now().timestamp() as u64) {
+        // A code is only accepted once, so an observed code cannot be replayed.
+        Some(step) => state.repository.use_totp_step(user_id, step as i64).await?,
+        None => {
+            let code_hash = state.token_hasher.hash(&normalize_recovery_code(&form_data.code));
+            state.repository.use_recovery_code(user_id, &code_hash).await?
+        }
+    };
+
+    if !accepted {
+        tracing::debug!("User login rejected, wrong TOTP code, username: {}", user.username());
+        if attempt >= MAX_TOTP_ATTEMPTS {
+            tracing::warn!("Second factor of {} locked after {} wrong codes", user.username(), MAX_TOTP_ATTEMPTS);
+            session.remove::<Uuid>(TOTP_PENDING_USER_KEY).await?;
+            return Ok(Redirect::to("/login?error=too_many_attempts"));
+        }
+        return Ok(Redirect::to("/login/totp?error=wrong_code"));
+    }
+
+    state.repository.reset_totp_attempts(user_id).await?;
+    session.remove::<Uuid>(TOTP_PENDING_USER_KEY).await?;
+    session.insert("user", user.clone()).await?;
+    tracing::debug!("user logged in with two-factor authentication, username: {}", user.username());
+    Ok(Redirect::to("/"))
+}
+
+async fn change_password(
+    user: AuthUser,
+    State(state): State<AppState>,
+    Form(form_data): Form<PasswordChangeFormData>
+) -> AppResult<impl IntoResponse> {
+    if form_data.password != form_data.password_confirmation {
+        return Err(AppError::BadRequest("Bad Request: Password confirmation must match password!"));
+    }
+
+    let stored_user = state
+        .repository
+        .get_user_by_id(user.id())
+        .await?
+        .ok_or(AppError::Unauthorized("Unauthorized"))?;
+    if !stored_user.password_matches(&form_data.current_password)? {
+        tracing::debug!("REJECTED password change: wrong current password, username: {}", user.username());
+        return Err(AppError::Forbidden("Forbidden: The current password is wrong!"));
+    }
+
+    let hashed_password = hash_password(form_data.password)?;
+    state.repository.set_user_password(user.id(), &hashed_password).await?;
+    tracing::info!("password changed, username: {}", user.username());
+    Ok(Redirect::to("/me"))
+}
+
+/// Sets a new password with a reset token issued by an admin.
+async fn reset_password(
+    State(state): State<AppState>,
+    Form(form_data): Form<PasswordResetFormData>
+) -> AppResult<impl IntoResponse> {
+    if form_data.password != form_data.password_confirmation {
+        return Err(AppError::BadRequest("Bad Request: Password confirmation must match password!"));
+    }
+
+    let token_hash = state.token_hasher.hash(form_data.token.trim());
+    let hashed_password = hash_password(form_data.password)?;
+    if !state.repository.reset_password(&token_hash, &hashed_password).await? {
+        tracing::debug!("REJECTED password reset: unknown, used or expired token");
+        return Err(AppError::Forbidden("Forbidden: The reset token is not valid!"));
+    }
+
+    Ok(Redirect::to("/login"))
+}
+
+/// Deletes the account of the user, along with everything they own, such as their records and
+/// API keys.
+async fn delete_account(
+    user: AuthUser,
+    session: Session,
+    State(state): State<AppState>,
+    Form(form_data): Form<AccountDeletionFormData>
+) -> AppResult<impl IntoResponse> {
+    let stored_user = state
+        .repository
+        .get_user_by_id(user.id())
+        .await?
+        .ok_or(AppError::Unauthorized("Unauthorized"))?;
+    if !stored_user.password_matches(&form_data.password)? {
+        tracing::debug!("REJECTED account deletion: wrong password, username: {}", user.username());
+        return Err(AppError::Forbidden("Forbidden: The password is wrong!"));
+    }
+    if user.role().is_admin() && state.repository.count_active_admins().await? <= 1 {
+        return Err(AppError::BadRequest("Bad Request: The last admin cannot delete their account!"));
+    }
+
+    state.repository.delete_user(user.id()).await?;
+    session.flush().await?;
+    tracing::info!("account deleted, username: {}", user.username());
+    Ok(Redirect::to("/"))
+}
+
 async fn logout(session: Session) -> impl IntoResponse {
     session.delete().await.expect("Failed to delete session");
     Redirect::to("/")
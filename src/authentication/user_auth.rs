@@ -3,12 +3,18 @@ use axum::extract::FromRequestParts;
 use axum::http::request::Parts;
 use axum::{Extension, RequestPartsExt};
 use tower_sessions::Session;
-use crate::authentication::user::User;
+use crate::authentication::user::{Role, User};
 use crate::error::AppError;
+use crate::state::AppState;
 
+/// The logged-in user.
+///
+/// The user is looked up again on every request, so changes to the role take effect at once,
+/// and a disabled, deleted or not yet approved user is logged out.
 pub(crate) struct AuthUser {
     id: Uuid,
     username: String,
+    role: Role,
 }
 
 impl AuthUser {
@@ -16,6 +22,7 @@ impl AuthUser {
         AuthUser {
             id: *user.id(),
             username: user.username().to_string(),
+            role: user.role(),
         }
     }
 
@@ -26,20 +33,61 @@ impl AuthUser {
     pub fn username(&self) -> &str {
         &self.username
     }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
 }
 
-impl<S: Send + Sync> FromRequestParts<S> for AuthUser {
+impl FromRequestParts<AppState> for AuthUser {
     type Rejection = AppError;
 
-    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
         let Extension(session) = parts
             .extract::<Extension<Session>>()
             .await
             .map_err(|_| AppError::InternalServerError("Session not found"))?;
 
-        match session.get::<User>("user").await? {
-            Some(user) => Ok(AuthUser::new(user)),
-            None => Err(AppError::Unauthorized("Unauthorized")),
+        let Some(user) = session.get::<User>("user").await? else {
+            return Err(AppError::Unauthorized("Unauthorized"));
+        };
+
+        match state.repository.get_user_by_id(*user.id()).await? {
+            Some(user) if !user.disabled() && !user.pending() => Ok(AuthUser::new(user)),
+            _ => {
+                session.flush().await?;
+                Err(AppError::Unauthorized("Unauthorized"))
+            }
+        }
+    }
+}
+
+/// A logged-in user allowed to change data, i.e. a member or an admin.
+pub(crate) struct MemberUser(pub AuthUser);
+
+impl FromRequestParts<AppState> for MemberUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let user = AuthUser::from_request_parts(parts, state).await?;
+        if !user.role().can_write() {
+            return Err(AppError::Forbidden("Viewers cannot make changes"));
         }
+        Ok(MemberUser(user))
     }
-}
\ No newline at end of file
+}
+
+/// A logged-in admin.
+pub(crate) struct AdminUser(pub AuthUser);
+
+impl FromRequestParts<AppState> for AdminUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let user = AuthUser::from_request_parts(parts, state).await?;
+        if !user.role().is_admin() {
+            return Err(AppError::Forbidden("Only admins may do this"));
+        }
+        Ok(AdminUser(user))
+    }
+}
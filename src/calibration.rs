@@ -0,0 +1,228 @@
+use crate::device::SensorType;
+use crate::temperature::{self, DecodedTemperature, Units};
+use rerec::Reading;
+use rerec::record::Record;
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// A quantity measured by a sensor. Temperatures are calibrated in degrees Celsius.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub(crate) enum Quantity {
+    Temperature,
+    Pressure,
+    Humidity,
+}
+
+/// A calibration of one quantity of a device.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub(crate) struct Calibration {
+    pub id: Uuid,
+    pub device_id: Uuid,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub details: CalibrationDetails,
+}
+
+impl Calibration {
+    pub fn new(device_id: Uuid, details: CalibrationDetails) -> Self {
+        Self { id: Uuid::new_v4(), device_id, details }
+    }
+}
+
+/// The editable properties of a calibration.
+///
+/// A calibrated value is the measured value multiplied by the gain plus the offset. The
+/// calibration applies to records from `valid_from` and up to, but not including, `valid_to`.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub(crate) struct CalibrationDetails {
+    pub quantity: Quantity,
+    #[serde(default = "default_gain")]
+    pub gain: f64,
+    #[serde(default)]
+    pub offset: f64,
+    pub valid_from: DateTime<Utc>,
+    pub valid_to: Option<DateTime<Utc>>,
+}
+
+fn default_gain() -> f64 {
+    1.0
+}
+
+impl CalibrationDetails {
+    /// Checks that the calibration is usable for a device of the given sensor type.
+    pub fn validate(&self, sensor: SensorType) -> Result<(), &'static str> {
+        if !self.gain.is_finite() || !self.offset.is_finite() {
+            return Err("gain and offset must be finite numbers");
+        }
+
+        if self.valid_to.is_some_and(|valid_to| valid_to <= self.valid_from) {
+            return Err("valid_to must be later than valid_from");
+        }
+
+        if sensor == SensorType::Ds18b20 && self.quantity != Quantity::Temperature {
+            return Err("a DS18B20 device only measures temperature");
+        }
+
+        Ok(())
+    }
+}
+
+/// The gain and offset of a calibration, as applicable to a particular record.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub(crate) struct Correction {
+    pub record_id: Uuid,
+    pub quantity: Quantity,
+    pub gain: f64,
+    pub offset: f64,
+}
+
+/// The calibrations applicable to a set of records, by record id.
+#[derive(Debug, Default)]
+pub(crate) struct RecordCalibrations(HashMap<Uuid, Vec<Correction>>);
+
+impl RecordCalibrations {
+    /// The value of a quantity of a record, calibrated if a calibration applies to it. `None` if
+    /// the record does not measure the quantity. Temperatures are in degrees Celsius.
+    pub fn value(&self, record: &Record, quantity: Quantity) -> Option<f64> {
+        let value = match (record.reading(), quantity) {
+            (Reading::BME280(reading), Quantity::Temperature) => f64::from(reading.temperature()),
+            (Reading::BME280(reading), Quantity::Pressure) => f64::from(reading.pressure()),
+            (Reading::BME280(reading), Quantity::Humidity) => f64::from(reading.humidity()),
+            (Reading::DS18B20(reading), Quantity::Temperature) => temperature::unrounded_celsius(reading.raw_reading()),
+            (Reading::DS18B20(_), Quantity::Pressure | Quantity::Humidity) => return None,
+        };
+        Some(self.apply(record.id(), quantity, value).unwrap_or(value))
+    }
+
+    /// The calibrated value of a quantity of a record, or `None` if no calibration applies.
+    fn apply(&self, record_id: Uuid, quantity: Quantity, value: f64) -> Option<f64> {
+        self.0
+            .get(&record_id)?
+            .iter()
+            .find(|correction| correction.quantity == quantity)
+            .map(|correction| temperature::round(value * correction.gain + correction.offset))
+    }
+}
+
+impl FromIterator<Correction> for RecordCalibrations {
+    fn from_iter<I: IntoIterator<Item = Correction>>(iter: I) -> Self {
+        let mut calibrations: HashMap<Uuid, Vec<Correction>> = HashMap::new();
+        for correction in iter {
+            calibrations.entry(correction.record_id).or_default().push(correction);
+        }
+        RecordCalibrations(calibrations)
+    }
+}
+
+/// The calibrated values of a record, next to the raw ones. Only quantities with an applicable
+/// calibration are included.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub(crate) enum CalibratedValues {
+    Bme280 {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        temperature: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pressure: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        humidity: Option<f64>,
+    },
+    /// The calibrated temperature in the requested units.
+    Ds18b20(DecodedTemperature),
+}
+
+impl CalibratedValues {
+    /// The calibrated values of the record, or `None` if no calibration applies to it.
+    pub fn new(record: &Record, calibrations: &RecordCalibrations, units: &Units) -> Option<Self> {
+        let calibrate = |quantity, value| calibrations.apply(record.id(), quantity, value);
+
+        match record.reading() {
+            Reading::BME280(reading) => {
+                let temperature = calibrate(Quantity::Temperature, f64::from(reading.temperature()));
+                let pressure = calibrate(Quantity::Pressure, f64::from(reading.pressure()));
+                let humidity = calibrate(Quantity::Humidity, f64::from(reading.humidity()));
+                (temperature.is_some() || pressure.is_some() || humidity.is_some())
+                    .then_some(CalibratedValues::Bme280 { temperature, pressure, humidity })
+            }
+            Reading::DS18B20(reading) => {
+                let celsius = temperature::unrounded_celsius(reading.raw_reading());
+                calibrate(Quantity::Temperature, celsius)
+                    .map(|celsius| CalibratedValues::Ds18b20(DecodedTemperature::from_celsius(celsius, units)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rerec::bme280::BME280;
+    use rerec::ds18b20::DS18B20;
+    use serde_json::json;
+
+    fn correction(record_id: Uuid, quantity: Quantity, gain: f64, offset: f64) -> Correction {
+        Correction { record_id, quantity, gain, offset }
+    }
+
+    #[test]
+    fn test_calibrate_bme280() {
+        let record = Record::new(Uuid::new_v4(), Utc::now(), Reading::BME280(BME280::new(21.5, 100000.0, 45.0)));
+        let calibrations: RecordCalibrations = [
+            correction(record.id(), Quantity::Temperature, 1.0, -0.3),
+            correction(record.id(), Quantity::Humidity, 1.02, 0.0),
+        ].into_iter().collect();
+
+        let calibrated = CalibratedValues::new(&record, &calibrations, &Units::default()).unwrap();
+        assert_eq!(serde_json::to_value(calibrated).unwrap(), json!({"temperature": 21.2, "humidity": 45.9}));
+    }
+
+    #[test]
+    fn test_calibrate_ds18b20() {
+        let record = Record::new(Uuid::new_v4(), Utc::now(), Reading::DS18B20(DS18B20::new("0000003e33d5".to_string(), 22123)));
+        let calibrations: RecordCalibrations = [correction(record.id(), Quantity::Temperature, 2.0, 0.5)].into_iter().collect();
+
+        let calibrated = CalibratedValues::new(&record, &calibrations, &Units::default()).unwrap();
+        assert_eq!(serde_json::to_value(calibrated).unwrap(), json!({"temperature_celsius": 44.746}));
+    }
+
+    #[test]
+    fn test_uncalibrated_record() {
+        let record = Record::new(Uuid::new_v4(), Utc::now(), Reading::DS18B20(DS18B20::new("0000003e33d5".to_string(), 22123)));
+        let calibrations: RecordCalibrations = [correction(Uuid::new_v4(), Quantity::Temperature, 2.0, 0.5)].into_iter().collect();
+
+        assert!(CalibratedValues::new(&record, &calibrations, &Units::default()).is_none());
+    }
+
+    #[test]
+    fn test_value() {
+        let record = Record::new(Uuid::new_v4(), Utc::now(), Reading::BME280(BME280::new(21.5, 100000.0, 45.0)));
+        let calibrations: RecordCalibrations = [correction(record.id(), Quantity::Temperature, 1.0, -0.3)].into_iter().collect();
+
+        assert_eq!(calibrations.value(&record, Quantity::Temperature), Some(21.2));
+        assert_eq!(calibrations.value(&record, Quantity::Humidity), Some(45.0));
+
+        let record = Record::new(Uuid::new_v4(), Utc::now(), Reading::DS18B20(DS18B20::new("0000003e33d5".to_string(), 22123)));
+        assert_eq!(calibrations.value(&record, Quantity::Temperature), Some(22.123));
+        assert_eq!(calibrations.value(&record, Quantity::Pressure), None);
+    }
+
+    #[test]
+    fn test_validate() {
+        let details = CalibrationDetails {
+            quantity: Quantity::Pressure,
+            gain: 1.0,
+            offset: 120.0,
+            valid_from: DateTime::from_timestamp(1772184765, 0).unwrap(),
+            valid_to: None,
+        };
+        assert!(details.validate(SensorType::Bme280).is_ok());
+        assert!(details.validate(SensorType::Ds18b20).is_err());
+
+        let expired = CalibrationDetails { valid_to: Some(details.valid_from), ..details };
+        assert!(expired.validate(SensorType::Bme280).is_err());
+    }
+}
@@ -0,0 +1,227 @@
+use crate::aggregation;
+use rerec::Reading;
+use rerec::record::Record;
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+use std::fmt;
+use uuid::Uuid;
+
+/// How long a device may be silent by default before it is reported.
+const DEFAULT_EXPECTED_INTERVAL_SECONDS: i64 = 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub(crate) enum SensorType {
+    Bme280,
+    Ds18b20,
+}
+
+impl SensorType {
+    pub fn of(record: &Record) -> Self {
+        match record.reading() {
+            Reading::BME280(_) => SensorType::Bme280,
+            Reading::DS18B20(_) => SensorType::Ds18b20,
+        }
+    }
+}
+
+/// A registered sensor, giving its records a friendly name.
+///
+/// DS18B20 records are linked to a device by its serial, which is the `device_name` the probe
+/// reports. BME280 records carry no identity of their own, so they are linked by the API key
+/// of the station submitting them.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub(crate) struct Device {
+    pub id: Uuid,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub details: DeviceDetails,
+}
+
+impl Device {
+    pub fn new(details: DeviceDetails) -> Self {
+        Self { id: Uuid::new_v4(), details }
+    }
+}
+
+/// The editable properties of a device.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub(crate) struct DeviceDetails {
+    pub sensor: SensorType,
+    /// The serial of a DS18B20 probe, as reported in the `device_name` of its records.
+    pub serial: Option<String>,
+    /// The API key a BME280 station submits its records with.
+    pub api_key_id: Option<Uuid>,
+    pub name: String,
+    #[serde(default)]
+    pub location: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+impl DeviceDetails {
+    /// Checks that the details identify a device in the way its sensor type requires.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.name.trim().is_empty() {
+            return Err("device name must not be empty");
+        }
+
+        match self.sensor {
+            SensorType::Ds18b20 if self.serial.as_deref().is_none_or(|serial| serial.trim().is_empty()) => {
+                Err("a DS18B20 device must have a serial")
+            }
+            SensorType::Ds18b20 if self.api_key_id.is_some() => {
+                Err("a DS18B20 device is identified by its serial, not by an API key")
+            }
+            SensorType::Bme280 if self.api_key_id.is_none() => {
+                Err("a BME280 device must have an API key")
+            }
+            SensorType::Bme280 if self.serial.is_some() => {
+                Err("a BME280 device is identified by its API key, not by a serial")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// When a source of records was last seen, and the device registered for it, if any.
+///
+/// A source is the `device_name` of a DS18B20 probe or the API key a BME280 station submits
+/// its records with. Registered devices which have never submitted a record are included
+/// without a `last_seen`.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub(crate) struct DeviceHealth {
+    pub sensor: SensorType,
+    pub device_name: Option<String>,
+    pub api_key_id: Option<Uuid>,
+    pub api_key_name: Option<String>,
+    pub device_id: Option<Uuid>,
+    pub name: Option<String>,
+    /// The latest timestamp of the records from the source.
+    pub last_seen: Option<DateTime<Utc>>,
+    /// Whether the source has been silent for longer than expected.
+    #[sqlx(skip)]
+    pub silent: bool,
+}
+
+impl DeviceHealth {
+    /// Marks the source as silent if it has not been seen within `expected_interval` of `now`.
+    pub fn check(mut self, now: DateTime<Utc>, expected_interval: ExpectedInterval) -> Self {
+        self.silent = self
+            .last_seen
+            .is_none_or(|last_seen| now.timestamp() - last_seen.timestamp() > expected_interval.seconds());
+        self
+    }
+}
+
+/// How often a device is expected to submit records, e.g. `15m` or `1h`, defaulting to an hour.
+///
+/// The interval is a positive integer followed by one of the units `s` (seconds), `m` (minutes),
+/// `h` (hours), `d` (days) or `w` (weeks).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub(crate) struct ExpectedInterval {
+    seconds: i64,
+}
+
+impl ExpectedInterval {
+    pub fn seconds(&self) -> i64 {
+        self.seconds
+    }
+}
+
+impl fmt::Display for ExpectedInterval {
+    /// Formats the interval in the largest unit it is a whole number of.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (unit, unit_seconds) = [("w", 7 * 24 * 60 * 60), ("d", 24 * 60 * 60), ("h", 60 * 60), ("m", 60)]
+            .into_iter()
+            .find(|(_, unit_seconds)| self.seconds % unit_seconds == 0)
+            .unwrap_or(("s", 1));
+        write!(f, "{}{unit}", self.seconds / unit_seconds)
+    }
+}
+
+impl Default for ExpectedInterval {
+    fn default() -> Self {
+        Self { seconds: DEFAULT_EXPECTED_INTERVAL_SECONDS }
+    }
+}
+
+impl TryFrom<String> for ExpectedInterval {
+    type Error = &'static str;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let seconds = aggregation::parse_seconds(&value)
+            .ok_or("expected_interval must be a positive integer followed by one of s, m, h, d or w")?;
+        Ok(Self { seconds })
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct HealthQuery {
+    #[serde(default)]
+    pub expected_interval: ExpectedInterval,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn details(sensor: SensorType, serial: Option<&str>, api_key_id: Option<Uuid>) -> DeviceDetails {
+        DeviceDetails {
+            sensor,
+            serial: serial.map(str::to_string),
+            api_key_id,
+            name: "Greenhouse north wall".to_string(),
+            location: String::new(),
+            description: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(details(SensorType::Ds18b20, Some("0000003e33d5"), None).validate().is_ok());
+        assert!(details(SensorType::Ds18b20, None, None).validate().is_err());
+        assert!(details(SensorType::Ds18b20, Some(" "), None).validate().is_err());
+        assert!(details(SensorType::Ds18b20, Some("0000003e33d5"), Some(Uuid::new_v4())).validate().is_err());
+        assert!(details(SensorType::Bme280, None, Some(Uuid::new_v4())).validate().is_ok());
+        assert!(details(SensorType::Bme280, None, None).validate().is_err());
+        assert!(details(SensorType::Bme280, Some("0000003e33d5"), Some(Uuid::new_v4())).validate().is_err());
+
+        let mut unnamed = details(SensorType::Bme280, None, Some(Uuid::new_v4()));
+        unnamed.name = String::new();
+        assert!(unnamed.validate().is_err());
+    }
+
+    #[test]
+    fn test_expected_interval() {
+        assert_eq!(ExpectedInterval::try_from("15m".to_string()).unwrap().seconds(), 900);
+        assert_eq!(ExpectedInterval::default().seconds(), 3600);
+        assert_eq!(ExpectedInterval::try_from("90m".to_string()).unwrap().to_string(), "90m");
+        assert_eq!(ExpectedInterval::try_from("120m".to_string()).unwrap().to_string(), "2h");
+        assert!(ExpectedInterval::try_from("0h".to_string()).is_err());
+        assert!(ExpectedInterval::try_from("soon".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_check_health() {
+        let now = DateTime::from_timestamp(1772184765, 0).unwrap();
+        let minutes_ago = |minutes: i64| DateTime::from_timestamp(now.timestamp() - minutes * 60, 0);
+        let health = |last_seen| DeviceHealth {
+            sensor: SensorType::Ds18b20,
+            device_name: Some("0000003e33d5".to_string()),
+            api_key_id: None,
+            api_key_name: None,
+            device_id: None,
+            name: None,
+            last_seen,
+            silent: false,
+        };
+        let expected_interval = ExpectedInterval::try_from("1h".to_string()).unwrap();
+
+        assert!(!health(minutes_ago(59)).check(now, expected_interval).silent);
+        assert!(health(minutes_ago(61)).check(now, expected_interval).silent);
+        assert!(health(None).check(now, expected_interval).silent);
+    }
+}
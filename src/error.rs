@@ -16,6 +16,7 @@ pub enum AppError {
 
     SqlxError(sqlx::Error),
     TeraError(tera::Error),
+    CsvError(csv::Error),
     SessionError(tower_sessions::session::Error),
     User(crate::authentication::user::UserError),
 }
@@ -35,6 +36,7 @@ impl AppError {
                 _ => StatusCode::INTERNAL_SERVER_ERROR,
             },
             AppError::TeraError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::CsvError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::SessionError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::User(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
@@ -51,6 +53,7 @@ impl AppError {
             
             AppError::SqlxError(_) => "database error",
             AppError::TeraError(_) => "template rendering error",
+            AppError::CsvError(_) => "CSV export error",
             AppError::SessionError(_) => "session error",
             AppError::User(_) => "user handling error",
         }
@@ -66,6 +69,7 @@ impl AppError {
             AppError::InternalServerError(_) => "INTERNAL_SERVER_ERROR",
             AppError::SqlxError(_) => "DATABASE_ERROR",
             AppError::TeraError(_) => "TEMPLATE_ERROR",
+            AppError::CsvError(_) => "CSV_ERROR",
             AppError::SessionError(_) => "SESSION_ERROR",
             AppError::User(_) => "USER_HANDLING_ERROR",
         }
@@ -85,6 +89,20 @@ impl AppError {
             other => AppError::SqlxError(other),
         }
     }
+
+    pub fn from_commit_device_error(error: sqlx::Error) -> Self {
+        use sqlx::error::ErrorKind;
+
+        match error {
+            sqlx::Error::Database(db_error) => match db_error.kind() {
+                ErrorKind::UniqueViolation => AppError::Conflict("a device with the same serial or API key is already registered"),
+                ErrorKind::ForeignKeyViolation => AppError::BadRequest("invalid device: references no API key of yours"),
+                ErrorKind::CheckViolation => AppError::BadRequest("invalid device: violates constraints"),
+                _ => AppError::SqlxError(sqlx::Error::Database(db_error)),
+            },
+            other => AppError::SqlxError(other),
+        }
+    }
 }
 
 impl IntoResponse for AppError {
@@ -92,6 +110,7 @@ impl IntoResponse for AppError {
         match &self {
             AppError::SqlxError(error) => tracing::warn!("AppError(Sqlx): {error}"),
             AppError::TeraError(error) => tracing::warn!("AppError(Tera): {error}"),
+            AppError::CsvError(error) => tracing::warn!("AppError(Csv): {error}"),
             AppError::SessionError(error) => tracing::warn!("AppError(Session): {error}"),
             AppError::User(error) => tracing::warn!("AppError(User): {error}"),
             _ => {}
@@ -119,6 +138,12 @@ impl From<tera::Error> for AppError {
     }
 }
 
+impl From<csv::Error> for AppError {
+    fn from(value: csv::Error) -> Self {
+        AppError::CsvError(value)
+    }
+}
+
 impl From<tower_sessions::session::Error> for AppError {
     fn from(value: tower_sessions::session::Error) -> Self {
         AppError::SessionError(value)
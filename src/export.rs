@@ -0,0 +1,278 @@
+use crate::error::AppError;
+use axum::body::Body;
+use axum::extract::{FromRequestParts, Query};
+use axum::http::request::Parts;
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use futures_util::{Stream, StreamExt};
+use rerec::Reading;
+use rerec::record::Record;
+use serde::{Deserialize, Serialize};
+
+const TEXT_CSV: &str = "text/csv";
+const APPLICATION_JSON: &str = "application/json";
+const APPLICATION_NDJSON: &str = "application/x-ndjson";
+/// RFC 3339 in UTC, with as many fractional digits as needed, matching the JSON representation.
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.fZ";
+
+/// The representation a client asked for, either by the `format` query parameter or,
+/// if that is absent, by the `Accept` header. JSON is the default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ResponseFormat {
+    #[default]
+    Json,
+    Csv,
+    /// Newline delimited JSON, one record per line, streamed without paging.
+    Ndjson,
+}
+
+impl ResponseFormat {
+    /// The first of the supported media types listed in an `Accept` header.
+    fn from_accept(accept: &str) -> Option<Self> {
+        accept
+            .split(',')
+            .map(|media_range| media_range.split(';').next().unwrap_or_default().trim())
+            .find_map(|media_type| match media_type {
+                TEXT_CSV => Some(ResponseFormat::Csv),
+                APPLICATION_JSON => Some(ResponseFormat::Json),
+                APPLICATION_NDJSON => Some(ResponseFormat::Ndjson),
+                _ => None,
+            })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct FormatQuery {
+    format: Option<ResponseFormat>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ResponseFormat {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(query) = Query::<FormatQuery>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| AppError::BadRequest("format must be one of 'json', 'csv' or 'ndjson'"))?;
+
+        if let Some(format) = query.format {
+            return Ok(format);
+        }
+
+        let format = parts
+            .headers
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .and_then(ResponseFormat::from_accept)
+            .unwrap_or_default();
+
+        Ok(format)
+    }
+}
+
+/// The columns of a CSV export, one per reading field of the exported sensor types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Columns {
+    /// Records of all sensor types, with empty cells for fields a sensor type does not have.
+    All,
+    Bme280,
+    Ds18b20,
+}
+
+impl Columns {
+    fn header(&self) -> &'static [&'static str] {
+        match self {
+            Columns::All => &["id", "timestamp", "sensor", "temperature", "pressure", "humidity", "device_name", "raw_reading"],
+            Columns::Bme280 => &["id", "timestamp", "temperature", "pressure", "humidity"],
+            Columns::Ds18b20 => &["id", "timestamp", "device_name", "raw_reading"],
+        }
+    }
+
+    fn row(&self, record: &Record) -> Vec<String> {
+        let id = record.id().to_string();
+        let timestamp = record.timestamp().format(TIMESTAMP_FORMAT).to_string();
+
+        match (self, record.reading()) {
+            (Columns::All, Reading::BME280(reading)) => vec![
+                id,
+                timestamp,
+                "BME280".to_string(),
+                reading.temperature().to_string(),
+                reading.pressure().to_string(),
+                reading.humidity().to_string(),
+                String::new(),
+                String::new(),
+            ],
+            (Columns::All, Reading::DS18B20(reading)) => vec![
+                id,
+                timestamp,
+                "DS18B20".to_string(),
+                String::new(),
+                String::new(),
+                String::new(),
+                reading.device_name().to_string(),
+                reading.raw_reading().to_string(),
+            ],
+            (Columns::Bme280, Reading::BME280(reading)) => vec![
+                id,
+                timestamp,
+                reading.temperature().to_string(),
+                reading.pressure().to_string(),
+                reading.humidity().to_string(),
+            ],
+            (Columns::Ds18b20, Reading::DS18B20(reading)) => vec![
+                id,
+                timestamp,
+                reading.device_name().to_string(),
+                reading.raw_reading().to_string(),
+            ],
+            (_, _) => vec![String::new(); self.header().len()],
+        }
+    }
+}
+
+/// One line of CSV, quoted and terminated as the `csv` crate does.
+fn csv_line<I, T>(fields: I) -> Result<Vec<u8>, csv::Error>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<[u8]>,
+{
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(fields)?;
+    writer
+        .into_inner()
+        .map_err(|error| csv::Error::from(error.into_error()))
+}
+
+/// Records as a CSV attachment, written to the client as they are read from the stream.
+///
+/// As with NDJSON, the status is sent before the first record, so an error while streaming
+/// cuts the response short instead of being reported.
+pub(crate) fn csv_response<S>(records: S, columns: Columns, filename: &'static str) -> Response
+where
+    S: Stream<Item = Result<Record, sqlx::Error>> + Send + 'static,
+{
+    let header = futures_util::stream::once(async move { csv_line(columns.header()) });
+    let rows = records.map(move |record| {
+        let record = record.map_err(|error| {
+            tracing::warn!("CSV stream aborted: {error}");
+            csv::Error::from(std::io::Error::other(error))
+        })?;
+        csv_line(columns.row(&record))
+    });
+    let lines = header
+        .chain(rows)
+        .map(|line| line.map_err(axum::BoxError::from));
+
+    let mut response = (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, HeaderValue::from_static("text/csv; charset=utf-8"))],
+        Body::from_stream(lines),
+    ).into_response();
+
+    if let Ok(disposition) = HeaderValue::from_str(&format!("attachment; filename=\"{filename}\"")) {
+        response.headers_mut().insert(header::CONTENT_DISPOSITION, disposition);
+    }
+
+    response
+}
+
+/// Records as newline delimited JSON, written to the client as they are read from the stream.
+///
+/// The status is sent before the first record, so an error while streaming cannot be reported
+/// as such. The response is cut short instead, which leaves the last line incomplete.
+pub(crate) fn ndjson_response<T, S>(records: S) -> Response
+where
+    T: Serialize,
+    S: Stream<Item = Result<T, sqlx::Error>> + Send + 'static,
+{
+    let lines = records.map(|record| {
+        let record = record.inspect_err(|error| tracing::warn!("NDJSON stream aborted: {error}"))?;
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        Ok::<_, axum::BoxError>(line)
+    });
+
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, HeaderValue::from_static(APPLICATION_NDJSON))],
+        Body::from_stream(lines),
+    ).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rerec::bme280::BME280;
+    use rerec::ds18b20::DS18B20;
+    use sqlx::types::chrono::DateTime;
+    use uuid::Uuid;
+
+    fn records() -> Vec<Record> {
+        let timestamp = DateTime::from_timestamp(1772184765, 0).unwrap();
+        vec![
+            Record::new(
+                Uuid::parse_str("8e9b1a33-05fb-48e3-86b6-21ddc873c06f").unwrap(),
+                timestamp,
+                Reading::BME280(BME280::new(21.5, 100000.0, 45.0)),
+            ),
+            Record::new(
+                Uuid::parse_str("7e9b1a33-05fb-48e3-86b6-21ddc873c06f").unwrap(),
+                timestamp,
+                Reading::DS18B20(DS18B20::new("0000003e33d5".to_string(), 22123)),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_from_accept() {
+        assert_eq!(ResponseFormat::from_accept("text/csv"), Some(ResponseFormat::Csv));
+        assert_eq!(ResponseFormat::from_accept("application/json"), Some(ResponseFormat::Json));
+        assert_eq!(ResponseFormat::from_accept("application/x-ndjson"), Some(ResponseFormat::Ndjson));
+        assert_eq!(ResponseFormat::from_accept("text/html, text/csv;q=0.9, application/json;q=0.8"), Some(ResponseFormat::Csv));
+        assert_eq!(ResponseFormat::from_accept("*/*"), None);
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_response() {
+        let records = futures_util::stream::iter(records().into_iter().map(Ok));
+        let response = ndjson_response(records);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], APPLICATION_NDJSON);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let lines: Vec<serde_json::Value> = body
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["id"], "7e9b1a33-05fb-48e3-86b6-21ddc873c06f");
+    }
+
+    async fn to_csv(records: Vec<Record>, columns: Columns) -> String {
+        let response = csv_response(futures_util::stream::iter(records.into_iter().map(Ok)), columns, "records.csv");
+        assert_eq!(response.headers()[header::CONTENT_DISPOSITION], "attachment; filename=\"records.csv\"");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_csv_response_all() {
+        let csv = to_csv(records(), Columns::All).await;
+        assert_eq!(csv, "\
+id,timestamp,sensor,temperature,pressure,humidity,device_name,raw_reading
+8e9b1a33-05fb-48e3-86b6-21ddc873c06f,2026-02-27T09:32:45Z,BME280,21.5,100000,45,,
+7e9b1a33-05fb-48e3-86b6-21ddc873c06f,2026-02-27T09:32:45Z,DS18B20,,,,0000003e33d5,22123
+");
+    }
+
+    #[tokio::test]
+    async fn test_csv_response_ds18b20() {
+        let records = records().split_off(1);
+        let csv = to_csv(records, Columns::Ds18b20).await;
+        assert_eq!(csv, "\
+id,timestamp,device_name,raw_reading
+7e9b1a33-05fb-48e3-86b6-21ddc873c06f,2026-02-27T09:32:45Z,0000003e33d5,22123
+");
+    }
+}
@@ -0,0 +1,162 @@
+use crate::device::SensorType;
+use crate::repository::Repository;
+use rerec::Reading;
+use rerec::record::Record;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// How many records a subscriber may fall behind before it misses records.
+const CAPACITY: usize = 1024;
+/// How long to wait before listening again after the connection to the database was lost.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// A newly committed record along with the user owning it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct CommittedRecord {
+    pub owner_id: Uuid,
+    pub record: Record,
+}
+
+/// Announces a committed record on the notification channel.
+///
+/// Postgres rejects payloads of 8000 bytes or more, so the record itself is not included: the
+/// feed reads it back when the notification arrives.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct RecordNotification {
+    pub id: Uuid,
+    pub owner_id: Uuid,
+    pub sensor: SensorType,
+}
+
+/// Live feed of newly committed records.
+///
+/// Records are announced as Postgres notifications, so records committed by any server sharing
+/// the database are included, and handed out to every subscriber in this server. Subscribers
+/// are responsible for only passing on records their client may read.
+#[derive(Clone)]
+pub(crate) struct RecordFeed {
+    sender: broadcast::Sender<CommittedRecord>,
+}
+
+impl RecordFeed {
+    /// Starts listening for committed records in the background.
+    pub fn start(repository: Repository) -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        tokio::spawn(forward_notifications(repository, sender.clone()));
+        Self { sender }
+    }
+
+    /// Records committed from now on. A subscriber too slow to keep up skips the records it
+    /// has fallen behind on.
+    pub fn subscribe(&self) -> broadcast::Receiver<CommittedRecord> {
+        self.sender.subscribe()
+    }
+}
+
+async fn forward_notifications(repository: Repository, sender: broadcast::Sender<CommittedRecord>) {
+    loop {
+        let mut listener = match repository.listen_records().await {
+            Ok(listener) => listener,
+            Err(error) => {
+                tracing::warn!("Unable to listen for committed records: {error}");
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+
+        loop {
+            let notification = match listener.recv().await {
+                Ok(notification) => notification,
+                Err(error) => {
+                    tracing::warn!("Stopped listening for committed records: {error}");
+                    break;
+                }
+            };
+
+            let notification = match serde_json::from_str::<RecordNotification>(notification.payload()) {
+                Ok(notification) => notification,
+                Err(error) => {
+                    tracing::warn!("Ignoring malformed record notification: {error}");
+                    continue;
+                }
+            };
+
+            // Nobody would receive the record, so it is not worth reading back.
+            if sender.receiver_count() == 0 {
+                continue;
+            }
+
+            match repository.get_committed_record(notification).await {
+                // Sending only fails if everyone unsubscribed meanwhile, in which case the record is of no interest.
+                Ok(Some(record)) => _ = sender.send(record),
+                Ok(None) => {}
+                Err(error) => tracing::warn!("Unable to read committed record {}: {error}", notification.id),
+            }
+        }
+
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Selects the records of interest to a subscriber of the feed.
+///
+/// An empty list of sensor types or device names does not restrict the records. As with the
+/// record listings, naming any device excludes BME280 records, as they have no device name.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, sqlx::FromRow)]
+pub(crate) struct FeedFilter {
+    /// Only include records of these sensor types. The parameter may be repeated.
+    #[serde(default)]
+    pub sensor: Vec<SensorType>,
+    /// Only include DS18B20 records from these devices. The parameter may be repeated.
+    #[serde(default)]
+    pub device_name: Vec<String>,
+}
+
+impl FeedFilter {
+    pub fn matches(&self, record: &Record) -> bool {
+        let sensor_matches = self.sensor.is_empty() || self.sensor.contains(&SensorType::of(record));
+        let device_matches = self.device_name.is_empty() || match record.reading() {
+            Reading::DS18B20(reading) => self.device_name.iter().any(|name| name == reading.device_name()),
+            Reading::BME280(_) => false,
+        };
+        sensor_matches && device_matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rerec::bme280::BME280;
+    use rerec::ds18b20::DS18B20;
+    use sqlx::types::chrono::Utc;
+    use uuid::Uuid;
+
+    fn bme280() -> Record {
+        Record::new(Uuid::new_v4(), Utc::now(), Reading::BME280(BME280::new(21.5, 100000.0, 45.0)))
+    }
+
+    fn ds18b20(device_name: &str) -> Record {
+        Record::new(Uuid::new_v4(), Utc::now(), Reading::DS18B20(DS18B20::new(device_name.to_string(), 22123)))
+    }
+
+    #[test]
+    fn test_empty_filter_matches_everything() {
+        let filter = FeedFilter::default();
+        assert!(filter.matches(&bme280()));
+        assert!(filter.matches(&ds18b20("0000003e33d5")));
+    }
+
+    #[test]
+    fn test_filter_by_sensor_and_device() {
+        let filter = FeedFilter { sensor: vec![SensorType::Bme280], device_name: vec![] };
+        assert!(filter.matches(&bme280()));
+        assert!(!filter.matches(&ds18b20("0000003e33d5")));
+
+        let filter = FeedFilter { sensor: vec![], device_name: vec!["0000003e33d5".to_string()] };
+        assert!(!filter.matches(&bme280()));
+        assert!(filter.matches(&ds18b20("0000003e33d5")));
+        assert!(!filter.matches(&ds18b20("000000a1b2c3")));
+    }
+}
@@ -5,17 +5,35 @@ use tower_sessions::SessionManagerLayer;
 use tower_sessions_sqlx_store::PostgresStore;
 use authentication::api_key;
 
+pub use authentication::registration::RegistrationMode;
+
+mod aggregation;
+mod alert;
 mod api;
 mod authentication;
+mod calibration;
+mod device;
 mod error;
+mod export;
+mod feed;
 mod http_security_headers;
+mod pagination;
 mod repository;
+mod share;
 mod state;
 mod status;
+mod temperature;
 mod web;
+mod webhook;
 
-pub async fn app(db_pool: sqlx::PgPool) -> axum::Router {
-    let state = state::AppState::new(db_pool.clone());
+/// The application, with API key tokens hashed using `api_key_secret` as key, and accounts
+/// registered as allowed by `registration_mode`.
+pub async fn app(
+    db_pool: sqlx::PgPool,
+    api_key_secret: &str,
+    registration_mode: RegistrationMode,
+) -> axum::Router {
+    let state = state::AppState::new(db_pool.clone(), api_key_secret, registration_mode);
     let session_store = PostgresStore::new(db_pool)
         .with_schema_name("auth")
         .unwrap()
@@ -17,7 +17,13 @@ async fn main() {
     let database_url =
         env::var("DATABASE_URL").expect("Environment variable 'DATABASE_URL' must be set");
     let db_pool = sqlx::PgPool::connect(&database_url).await.unwrap();
-    let app = herodot::app(db_pool).await;
+    let api_key_secret =
+        env::var("API_KEY_SECRET").expect("Environment variable 'API_KEY_SECRET' must be set");
+    let registration_mode = match env::var("REGISTRATION_MODE") {
+        Ok(mode) => mode.parse().expect("Environment variable 'REGISTRATION_MODE' must be valid"),
+        Err(_) => herodot::RegistrationMode::default(),
+    };
+    let app = herodot::app(db_pool, &api_key_secret, registration_mode).await;
 
     let port = env::var("PORT").expect("Environment variable 'PORT' must be set");
     let addr = SocketAddr::from((std::net::Ipv4Addr::UNSPECIFIED, port.parse().unwrap()));
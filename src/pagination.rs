@@ -0,0 +1,154 @@
+use base64::Engine;
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use rerec::record::Record;
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// Position in a listing of records ordered by timestamp and id.
+///
+/// The cursor points at the last record of a page, and the next page starts right after it.
+/// Records sharing a timestamp are told apart by their id, so no record is skipped or repeated
+/// at a page boundary. Clients only ever see the opaque, encoded form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct Cursor {
+    timestamp: DateTime<Utc>,
+    id: Uuid,
+}
+
+impl Cursor {
+    pub fn new(timestamp: DateTime<Utc>, id: Uuid) -> Self {
+        Self { timestamp, id }
+    }
+
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn encode(&self) -> String {
+        let plain = format!("{}_{}", self.timestamp.timestamp_micros(), self.id);
+        BASE64_URL_SAFE_NO_PAD.encode(plain)
+    }
+
+    pub fn decode(encoded: &str) -> Result<Self, CursorError> {
+        let bytes = BASE64_URL_SAFE_NO_PAD
+            .decode(encoded)
+            .map_err(|_| CursorError)?;
+        let plain = String::from_utf8(bytes).map_err(|_| CursorError)?;
+        let (micros, id) = plain.split_once('_').ok_or(CursorError)?;
+        let micros = micros.parse::<i64>().map_err(|_| CursorError)?;
+        let timestamp = DateTime::from_timestamp_micros(micros).ok_or(CursorError)?;
+        let id = Uuid::parse_str(id).map_err(|_| CursorError)?;
+        Ok(Self { timestamp, id })
+    }
+}
+
+impl From<&Record> for Cursor {
+    fn from(record: &Record) -> Self {
+        Cursor::new(record.timestamp(), record.id())
+    }
+}
+
+impl TryFrom<String> for Cursor {
+    type Error = CursorError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Cursor::decode(&value)
+    }
+}
+
+impl From<Cursor> for String {
+    fn from(value: Cursor) -> Self {
+        value.encode()
+    }
+}
+
+#[derive(Debug)]
+pub struct CursorError;
+
+impl std::fmt::Display for CursorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid cursor")
+    }
+}
+
+impl std::error::Error for CursorError {}
+
+/// A single page of records along with the cursor for the next page, if there is one.
+#[derive(Debug, Serialize)]
+pub(crate) struct Page<T = Record> {
+    pub records: Vec<T>,
+    pub next_cursor: Option<Cursor>,
+}
+
+impl<T> Page<T> {
+    /// Converts the records of the page, keeping the cursor.
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            records: self.records.into_iter().map(f).collect(),
+            next_cursor: self.next_cursor,
+        }
+    }
+}
+
+impl Page {
+    /// Builds a page from records fetched with a limit one higher than the page size.
+    /// The surplus record only tells that another page exists; it is not part of this page.
+    pub fn from_overfetched(mut records: Vec<Record>, page_size: usize) -> Self {
+        let next_cursor = if records.len() > page_size {
+            records.truncate(page_size);
+            records.last().map(Cursor::from)
+        } else {
+            None
+        };
+        Self { records, next_cursor }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rerec::Reading;
+    use rerec::ds18b20::DS18B20;
+
+    fn record(micros: i64) -> Record {
+        let timestamp = DateTime::from_timestamp_micros(micros).unwrap();
+        let reading = Reading::DS18B20(DS18B20::new("0000003e33d5".to_string(), 22123));
+        Record::new(Uuid::new_v4(), timestamp, reading)
+    }
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let timestamp = DateTime::parse_from_rfc3339("2026-02-27T09:32:45.123456Z").unwrap().to_utc();
+        let id = Uuid::parse_str("7e9b1a33-05fb-48e3-86b6-21ddc873c06f").unwrap();
+        let cursor = Cursor::new(timestamp, id);
+
+        assert_eq!(Cursor::decode(&cursor.encode()).unwrap(), cursor);
+    }
+
+    #[test]
+    fn test_cursor_decode_rejects_garbage() {
+        assert!(Cursor::decode("").is_err());
+        assert!(Cursor::decode("not a cursor!").is_err());
+        assert!(Cursor::decode(&BASE64_URL_SAFE_NO_PAD.encode("12345_not-a-uuid")).is_err());
+    }
+
+    #[test]
+    fn test_page_from_overfetched() {
+        let records = vec![record(1), record(2), record(3)];
+        let last_on_page = Cursor::from(&records[1]);
+
+        let page = Page::from_overfetched(records, 2);
+        assert_eq!(page.records.len(), 2);
+        assert_eq!(page.next_cursor, Some(last_on_page));
+
+        let page = Page::from_overfetched(vec![record(1), record(2)], 2);
+        assert_eq!(page.records.len(), 2);
+        assert_eq!(page.next_cursor, None);
+    }
+}
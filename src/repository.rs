@@ -1,16 +1,61 @@
+use crate::alert::{AlertEvent, AlertHistoryEntry, AlertRule};
 use crate::authentication::api_key::ApiKey;
-use crate::authentication::user::User;
+use crate::authentication::invite::Invite;
+use crate::authentication::user::{Role, User, UserSummary};
+use crate::calibration::{Calibration, Correction, RecordCalibrations};
+use crate::device::{Device, DeviceHealth, SensorType};
+use crate::feed::{CommittedRecord, RecordNotification};
+use crate::share::RecordShare;
+use crate::webhook::{Webhook, WebhookDelivery};
+use futures_util::Stream;
 use rerec::Reading;
 use rerec::bme280::BME280;
 use rerec::ds18b20::DS18B20;
 use rerec::record::Record;
-use sqlx::{PgPool, QueryBuilder};
+use sqlx::postgres::{PgListener, PgRow};
+use sqlx::{Acquire, FromRow, PgConnection, PgPool, Postgres, QueryBuilder, Row};
 use sqlx::types::chrono;
 use uuid::Uuid;
-use crate::api::RecordFilter;
+use crate::aggregation::{AggregateFilter, AggregateRow};
+use crate::api::{Order, RecordFilter};
+use crate::pagination::{Cursor, Page};
 
-const DEFAULT_LIMIT: u32 = 100;
+pub(crate) const DEFAULT_LIMIT: u32 = 100;
 const HARD_LIMIT: u32 = 5000;
+/// How many records a streamed query reads at a time.
+const STREAM_PAGE_SIZE: i64 = 1000;
+
+/// The Postgres notification channel on which every committed record is announced.
+/// The payload is the JSON serialization of a [`RecordNotification`].
+const RECORDS_CHANNEL: &str = "records";
+
+/// All records of all sensor types as one relation.
+/// The `sensor` column tells which sensor type a row holds, and the columns of the other sensor
+/// types are `NULL` for that row.
+const ALL_RECORDS: &str = r#"
+SELECT 'bme280' AS sensor, id, owner_id, api_key_id, timestamp, temperature, pressure, humidity, NULL::text AS device_name, NULL::integer AS raw_reading FROM records.bme280
+UNION ALL
+SELECT 'ds18b20' AS sensor, id, owner_id, NULL::uuid, timestamp, NULL::real, NULL::real, NULL::real, device_name, raw_reading FROM records.ds18b20
+"#;
+
+/// The rows a record query selects from.
+#[derive(Debug, Clone, Copy)]
+enum RecordSource {
+    /// The records of every sensor, as selected by [`ALL_RECORDS`].
+    All,
+    Bme280,
+    Ds18b20,
+}
+
+impl RecordSource {
+    fn push_table(&self, query_builder: &mut QueryBuilder<'_, Postgres>) {
+        match self {
+            RecordSource::All => query_builder.push("(").push(ALL_RECORDS).push(")"),
+            RecordSource::Bme280 => query_builder.push("records.bme280"),
+            RecordSource::Ds18b20 => query_builder.push("records.ds18b20"),
+        };
+    }
+}
 
 #[derive(Clone)]
 pub(crate) struct Repository {
@@ -41,6 +86,14 @@ impl From<Ds18b20Record> for Reading {
     }
 }
 
+/// A DS18B20 record along with the name of its registered device, if any.
+#[derive(sqlx::FromRow)]
+struct NamedDs18b20Record {
+    #[sqlx(flatten)]
+    record: Ds18b20Record,
+    device: Option<String>,
+}
+
 #[derive(sqlx::FromRow, serde::Serialize)]
 struct Bme280Record {
     id: Uuid,
@@ -70,60 +123,183 @@ impl From<Bme280Record> for Reading {
     }
 }
 
+#[derive(sqlx::FromRow)]
+struct Bme280Aggregate {
+    bucket: chrono::DateTime<chrono::Utc>,
+    count: i64,
+    temperature_avg: f32,
+    temperature_min: f32,
+    temperature_max: f32,
+    pressure_avg: f32,
+    pressure_min: f32,
+    pressure_max: f32,
+    humidity_avg: f32,
+    humidity_min: f32,
+    humidity_max: f32,
+}
+
+impl Bme280Aggregate {
+    fn into_rows(self) -> [AggregateRow; 3] {
+        let row = |metric, avg, min, max| AggregateRow {
+            bucket: self.bucket,
+            device_name: None,
+            metric,
+            avg: Some(avg),
+            min: Some(min),
+            max: Some(max),
+            count: Some(self.count),
+        };
+        [
+            row("temperature", self.temperature_avg, self.temperature_min, self.temperature_max),
+            row("pressure", self.pressure_avg, self.pressure_min, self.pressure_max),
+            row("humidity", self.humidity_avg, self.humidity_min, self.humidity_max),
+        ]
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct Ds18b20Aggregate {
+    bucket: chrono::DateTime<chrono::Utc>,
+    device_name: String,
+    count: i64,
+    temperature_avg: f32,
+    temperature_min: f32,
+    temperature_max: f32,
+}
+
+impl From<Ds18b20Aggregate> for AggregateRow {
+    fn from(value: Ds18b20Aggregate) -> Self {
+        AggregateRow {
+            bucket: value.bucket,
+            device_name: Some(value.device_name),
+            metric: "temperature",
+            avg: Some(value.temperature_avg),
+            min: Some(value.temperature_min),
+            max: Some(value.temperature_max),
+            count: Some(value.count),
+        }
+    }
+}
+
+/// A row of [`ALL_RECORDS`], decoded according to its sensor type.
+struct RecordRow(Record);
+
+impl<'r> FromRow<'r, PgRow> for RecordRow {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        let sensor: &str = row.try_get("sensor")?;
+        let record = match sensor {
+            "bme280" => Record::from(Bme280Record::from_row(row)?),
+            "ds18b20" => Record::from(Ds18b20Record::from_row(row)?),
+            other => {
+                return Err(sqlx::Error::ColumnDecode {
+                    index: "sensor".to_string(),
+                    source: format!("unknown sensor type '{other}'").into(),
+                });
+            }
+        };
+        Ok(RecordRow(record))
+    }
+}
+
+impl From<RecordRow> for Record {
+    fn from(value: RecordRow) -> Self {
+        value.0
+    }
+}
+
 impl Repository {
     pub(crate) fn new(db_pool: PgPool) -> Self {
         Self { db_pool }
     }
 
-    pub(crate) async fn commit_record(&self, record: Record) -> Result<Uuid, sqlx::Error> {
-        let record_id = record.id();
-        let timestamp = record.timestamp();
-        let reading = record.reading();
-
-        match reading {
-            Reading::BME280(reading) => {
-                let temperature = reading.temperature();
-                let pressure = reading.pressure();
-                let humidity = reading.humidity();
-
-                sqlx::query(r#"INSERT INTO records.bme280 (id, temperature, pressure, humidity, timestamp) VALUES ($1, $2, $3, $4, $5)"#)
-                    .bind(record_id)
-                    .bind(temperature)
-                    .bind(pressure)
-                    .bind(humidity)
-                    .bind(timestamp)
-                    .execute(&self.db_pool)
-                    .await?;
+    /// Commits a record submitted with the given API key. The record is owned by the owner of
+    /// the key.
+    pub(crate) async fn commit_record(&self, record: &Record, api_key: &ApiKey) -> Result<Uuid, sqlx::Error> {
+        let mut transaction = self.db_pool.begin().await?;
+        let record_id = insert_record(&mut transaction, record, api_key).await?;
+        transaction.commit().await?;
+        Ok(record_id)
+    }
 
-                Ok(record_id)
-            }
-            Reading::DS18B20(reading) => {
-                let device_name = reading.device_name();
-                let raw_reading = reading.raw_reading();
-
-                sqlx::query(r#"INSERT INTO records.ds18b20 (id, device_name, raw_reading, timestamp) VALUES ($1, $2, $3, $4)"#)
-                    .bind(record_id)
-                    .bind(device_name)
-                    .bind(raw_reading)
-                    .bind(timestamp)
-                    .execute(&self.db_pool)
-                    .await?;
+    /// Commits a batch of records in a single transaction.
+    ///
+    /// Each record is inserted within its own savepoint, so a record rejected by a constraint
+    /// (e.g. an already existing id) does not abort the remaining records. The returned list
+    /// holds the outcome for each record in the order given. Any other error rolls back the
+    /// entire batch.
+    pub(crate) async fn commit_records(
+        &self,
+        records: &[Record],
+        api_key: &ApiKey,
+    ) -> Result<Vec<Result<Uuid, sqlx::Error>>, sqlx::Error> {
+        let mut transaction = self.db_pool.begin().await?;
+        let mut outcomes = Vec::with_capacity(records.len());
 
-                Ok(record_id)
+        for record in records {
+            let mut savepoint = (&mut *transaction).begin().await?;
+            match insert_record(&mut savepoint, record, api_key).await {
+                Ok(record_id) => {
+                    savepoint.commit().await?;
+                    outcomes.push(Ok(record_id));
+                }
+                Err(error) if is_constraint_violation(&error) => {
+                    savepoint.rollback().await?;
+                    outcomes.push(Err(error));
+                }
+                Err(error) => return Err(error),
             }
         }
+
+        transaction.commit().await?;
+
+        Ok(outcomes)
+    }
+
+    /// A listener receiving a notification for every record committed from now on, by this or
+    /// any other server sharing the database. Notifications are only sent once the transaction
+    /// inserting the record commits.
+    pub(crate) async fn listen_records(&self) -> Result<PgListener, sqlx::Error> {
+        let mut listener = PgListener::connect_with(&self.db_pool).await?;
+        listener.listen(RECORDS_CHANNEL).await?;
+        Ok(listener)
+    }
+
+    /// The announced record, regardless of who may read it, or `None` if it has been deleted
+    /// since.
+    pub(crate) async fn get_committed_record(
+        &self,
+        notification: RecordNotification,
+    ) -> Result<Option<CommittedRecord>, sqlx::Error> {
+        let RecordNotification { id, owner_id, sensor } = notification;
+        let record = match sensor {
+            SensorType::Bme280 => sqlx::query_as::<_, Bme280Record>(r#"SELECT id, temperature, pressure, humidity, timestamp FROM records.bme280 WHERE id = $1 AND owner_id = $2"#)
+                .bind(id)
+                .bind(owner_id)
+                .fetch_optional(&self.db_pool)
+                .await?
+                .map(Record::from),
+            SensorType::Ds18b20 => sqlx::query_as::<_, Ds18b20Record>(r#"SELECT id, device_name, raw_reading, timestamp FROM records.ds18b20 WHERE id = $1 AND owner_id = $2"#)
+                .bind(id)
+                .bind(owner_id)
+                .fetch_optional(&self.db_pool)
+                .await?
+                .map(Record::from),
+        };
+
+        Ok(record.map(|record| CommittedRecord { owner_id, record }))
     }
 
     pub(crate) async fn get_record_by_id(
         &self,
         record_id: Uuid,
+        viewer_id: Uuid,
     ) -> Result<Option<Record>, sqlx::Error> {
-        let bme280 = self.get_bme280_record_by_id(record_id).await?;
+        let bme280 = self.get_bme280_record_by_id(record_id, viewer_id).await?;
         if let Some(bme280) = bme280 {
             return Ok(Some(bme280));
         }
 
-        let ds18b20 = self.get_ds18b20_record_by_id(record_id).await?;
+        let ds18b20 = self.get_ds18b20_record_by_id(record_id, viewer_id).await?;
         if let Some(ds18b20) = ds18b20 {
             return Ok(Some(ds18b20));
         }
@@ -131,13 +307,14 @@ impl Repository {
         Ok(None)
     }
 
-    pub(crate) async fn get_records(&self) -> Result<Vec<Record>, sqlx::Error> {
+    /// Every record the viewer may read.
+    pub(crate) async fn get_records(&self, viewer_id: Uuid) -> Result<Vec<Record>, sqlx::Error> {
         let mut records: Vec<Record> = Vec::new();
-        let bme280_records = self.get_all_bme280_records().await?;
-        let ds18b20_records = self.get_all_ds18b20_records().await?;
+        let bme280_records = self.get_all_bme280_records(viewer_id).await?;
+        let ds18b20_records = self.get_all_ds18b20_records(viewer_id).await?;
 
-        records.extend(bme280_records.into_iter());
-        records.extend(ds18b20_records.into_iter());
+        records.extend(bme280_records);
+        records.extend(ds18b20_records);
 
         Ok(records)
     }
@@ -145,9 +322,11 @@ impl Repository {
     pub(crate) async fn get_bme280_record_by_id(
         &self,
         record_id: Uuid,
+        viewer_id: Uuid,
     ) -> Result<Option<Record>, sqlx::Error> {
-        let record = sqlx::query_as::<_, Bme280Record>(r#"SELECT id, temperature, pressure, humidity, timestamp FROM records.bme280 WHERE id = $1"#)
+        let record = sqlx::query_as::<_, Bme280Record>(&format!(r#"SELECT id, temperature, pressure, humidity, timestamp FROM records.bme280 WHERE id = $1 AND owner_id IN ({})"#, visible_owners(2)))
             .bind(record_id)
+            .bind(viewer_id)
             .fetch_optional(&self.db_pool)
             .await?;
 
@@ -160,10 +339,12 @@ impl Repository {
         }
     }
 
-    pub(crate) async fn get_all_bme280_records(&self) -> Result<Vec<Record>, sqlx::Error> {
-        let records = sqlx::query_as::<_, Bme280Record>(
-            r#"SELECT id, temperature, pressure, humidity, timestamp FROM records.bme280"#,
-        )
+    pub(crate) async fn get_all_bme280_records(&self, viewer_id: Uuid) -> Result<Vec<Record>, sqlx::Error> {
+        let records = sqlx::query_as::<_, Bme280Record>(&format!(
+            r#"SELECT id, temperature, pressure, humidity, timestamp FROM records.bme280 WHERE owner_id IN ({})"#,
+            visible_owners(1),
+        ))
+        .bind(viewer_id)
         .fetch_all(&self.db_pool)
         .await?;
 
@@ -172,36 +353,66 @@ impl Repository {
         Ok(records)
     }
 
+    /// Records of all sensor types matching the filter that the viewer may read, in
+    /// chronological order.
     pub(crate) async fn get_record_by_filter(
         &self,
-        filter: &RecordFilter
-    ) -> Result<Vec<Record>, sqlx::Error> {
-        let mut records: Vec<Record> = Vec::new();
+        filter: &RecordFilter,
+        viewer_id: Uuid,
+    ) -> Result<Page, sqlx::Error> {
+        let mut query_builder = QueryBuilder::new("SELECT * FROM (");
+        query_builder.push(ALL_RECORDS);
+        query_builder.push(") AS records");
+        let page_size = push_filter(&mut query_builder, RecordSource::All, filter, viewer_id);
+
+        let records = query_builder.build_query_as::<RecordRow>()
+            .fetch_all(&self.db_pool)
+            .await?;
 
-        let bme280_records = self.get_bme280_by_filter(filter).await?;
-        let ds18b20_records = self.get_ds18b20_by_filter(filter).await?;
+        let records: Vec<Record> = records.into_iter().map(Record::from).collect();
 
-        records.extend(bme280_records.into_iter());
-        records.extend(ds18b20_records.into_iter());
+        Ok(Page::from_overfetched(records, page_size))
+    }
 
-        Ok(records)
+    /// Records of all sensor types matching the filter, streamed one by one as they are read
+    /// from the database.
+    pub(crate) fn stream_records_by_filter(
+        &self,
+        filter: RecordFilter,
+        viewer_id: Uuid,
+    ) -> impl Stream<Item = Result<Record, sqlx::Error>> + Send + 'static {
+        let select = format!("SELECT * FROM ({ALL_RECORDS}) AS records");
+        self.stream_by_filter::<RecordRow>(select, RecordSource::All, filter, viewer_id)
+    }
+
+    /// The most recent record of every sensor among the records the viewer may read.
+    ///
+    /// A DS18B20 probe is told apart by its device name and a BME280 station by the API key it
+    /// submits its records with. Sensors of different owners are told apart even if they report
+    /// the same device name.
+    pub(crate) async fn get_latest_records(&self, viewer_id: Uuid) -> Result<Vec<Record>, sqlx::Error> {
+        let mut query_builder = QueryBuilder::new("SELECT DISTINCT ON (sensor, owner_id, api_key_id, device_name) * FROM (");
+        query_builder.push(ALL_RECORDS);
+        query_builder.push(") AS records WHERE TRUE");
+        push_visible_to(&mut query_builder, viewer_id);
+        query_builder.push(" ORDER BY sensor, owner_id, api_key_id, device_name, timestamp DESC, id DESC");
+
+        let records = query_builder.build_query_as::<RecordRow>()
+            .fetch_all(&self.db_pool)
+            .await?;
+
+        let records: Vec<Record> = records.into_iter().map(Record::from).collect();
 
+        Ok(records)
     }
 
     pub(crate) async fn get_bme280_by_filter(
         &self,
         filter: &RecordFilter,
-    ) -> Result<Vec<Record>, sqlx::Error> {
-        let limit = limit(filter.limit);
+        viewer_id: Uuid,
+    ) -> Result<Page, sqlx::Error> {
         let mut query_builder = QueryBuilder::new(r#"SELECT id, temperature, pressure, humidity, timestamp FROM records.bme280"#);
-        query_builder.push(" WHERE TRUE ");
-
-        if let Some(from) = filter.from {
-            query_builder.push(" AND timestamp >= ").push_bind(from);
-        }
-
-        query_builder.push(" ORDER BY timestamp ASC");
-        query_builder.push(" LIMIT ").push_bind(limit);
+        let page_size = push_filter(&mut query_builder, RecordSource::Bme280, filter, viewer_id);
 
         let records = query_builder.build_query_as::<Bme280Record>()
             .fetch_all(&self.db_pool)
@@ -209,41 +420,151 @@ impl Repository {
 
         let records: Vec<Record> = records.into_iter().map(Record::from).collect();
 
-        Ok(records)
+        Ok(Page::from_overfetched(records, page_size))
     }
 
     pub(crate) async fn get_ds18b20_by_filter(
         &self,
-        filter: &RecordFilter
-    ) -> Result<Vec<Record>, sqlx::Error> {
-        let limit = limit(filter.limit);
+        filter: &RecordFilter,
+        viewer_id: Uuid,
+    ) -> Result<Page, sqlx::Error> {
         let mut query_builder = QueryBuilder::new(r#"SELECT id, device_name, raw_reading, timestamp FROM records.ds18b20"#);
-        query_builder.push(" WHERE TRUE ");
+        let page_size = push_filter(&mut query_builder, RecordSource::Ds18b20, filter, viewer_id);
+
+        let records = query_builder.build_query_as::<Ds18b20Record>()
+            .fetch_all(&self.db_pool)
+            .await?;
+
+        let records: Vec<Record> = records.into_iter().map(Record::from).collect();
+
+        Ok(Page::from_overfetched(records, page_size))
+    }
 
-        if let Some(from) = filter.from {
-            query_builder.push(" AND timestamp >= ").push_bind(from);
+    pub(crate) fn stream_bme280_by_filter(
+        &self,
+        filter: RecordFilter,
+        viewer_id: Uuid,
+    ) -> impl Stream<Item = Result<Record, sqlx::Error>> + Send + 'static {
+        let select = r#"SELECT id, temperature, pressure, humidity, timestamp FROM records.bme280"#.to_string();
+        self.stream_by_filter::<Bme280Record>(select, RecordSource::Bme280, filter, viewer_id)
+    }
+
+    pub(crate) fn stream_ds18b20_by_filter(
+        &self,
+        filter: RecordFilter,
+        viewer_id: Uuid,
+    ) -> impl Stream<Item = Result<Record, sqlx::Error>> + Send + 'static {
+        let select = r#"SELECT id, device_name, raw_reading, timestamp FROM records.ds18b20"#.to_string();
+        self.stream_by_filter::<Ds18b20Record>(select, RecordSource::Ds18b20, filter, viewer_id)
+    }
+
+    /// Streams the records selected by the query, narrowed down by the filter.
+    ///
+    /// Unlike the paged queries, the hard limit does not apply: all matching records are
+    /// returned unless the filter has a limit of its own. The records are read a page of
+    /// [`STREAM_PAGE_SIZE`] at a time, each page continuing after the last record of the
+    /// previous one, so the result is never held in memory as a whole. No connection of the
+    /// pool is kept in between pages, e.g. while the client is slow to receive them or the
+    /// calibrations of a page are looked up. Records committed while streaming may be included.
+    fn stream_by_filter<R>(
+        &self,
+        select: String,
+        source: RecordSource,
+        mut filter: RecordFilter,
+        viewer_id: Uuid,
+    ) -> impl Stream<Item = Result<Record, sqlx::Error>> + Send + 'static
+    where
+        R: for<'r> FromRow<'r, PgRow> + Into<Record> + Send + Unpin + 'static,
+    {
+        let db_pool = self.db_pool.clone();
+
+        async_stream::try_stream! {
+            let mut remaining = filter.limit.map(i64::from);
+            while remaining.is_none_or(|remaining| remaining > 0) {
+                let page_size = remaining.map_or(STREAM_PAGE_SIZE, |remaining| remaining.min(STREAM_PAGE_SIZE));
+                let mut query_builder = QueryBuilder::new(select.as_str());
+                push_conditions(&mut query_builder, source, &filter, viewer_id);
+                query_builder.push(" LIMIT ").push_bind(page_size);
+
+                let records = query_builder.build_query_as::<R>()
+                    .fetch_all(&db_pool)
+                    .await?;
+                let records: Vec<Record> = records.into_iter().map(Into::into).collect();
+
+                let Some(last) = records.last() else {
+                    break;
+                };
+                filter.cursor = Some(Cursor::from(last));
+                remaining = remaining.map(|remaining| remaining - records.len() as i64);
+                let exhausted = (records.len() as i64) < page_size;
+
+                for record in records {
+                    yield record;
+                }
+                if exhausted {
+                    break;
+                }
+            }
         }
+    }
 
-        query_builder.push(" ORDER BY timestamp ASC");
-        query_builder.push(" LIMIT ").push_bind(limit);
+    /// Aggregates of the BME280 metrics the viewer may read per time bucket, in chronological
+    /// order.
+    pub(crate) async fn get_bme280_aggregate(
+        &self,
+        filter: &AggregateFilter,
+        viewer_id: Uuid,
+    ) -> Result<Vec<AggregateRow>, sqlx::Error> {
+        let mut query_builder = QueryBuilder::new("SELECT date_bin(");
+        query_builder.push_bind(filter.bucket.interval());
+        query_builder.push(r#", timestamp, TIMESTAMPTZ 'epoch') AS bucket, count(*) AS count,
+    avg(temperature)::real AS temperature_avg, min(temperature) AS temperature_min, max(temperature) AS temperature_max,
+    avg(pressure)::real AS pressure_avg, min(pressure) AS pressure_min, max(pressure) AS pressure_max,
+    avg(humidity)::real AS humidity_avg, min(humidity) AS humidity_min, max(humidity) AS humidity_max
+FROM records.bme280"#);
+        push_aggregate_filter(&mut query_builder, filter, viewer_id, "bucket");
 
-        let records = query_builder.build_query_as::<Ds18b20Record>()
+        let aggregates = query_builder.build_query_as::<Bme280Aggregate>()
             .fetch_all(&self.db_pool)
             .await?;
 
-        let records: Vec<Record> = records.into_iter().map(Record::from).collect();
+        Ok(aggregates.into_iter().flat_map(Bme280Aggregate::into_rows).collect())
+    }
 
-        Ok(records)
+    /// Aggregates of the DS18B20 temperatures (in degrees Celsius) the viewer may read per time
+    /// bucket and device, in chronological order.
+    pub(crate) async fn get_ds18b20_aggregate(
+        &self,
+        filter: &AggregateFilter,
+        viewer_id: Uuid,
+    ) -> Result<Vec<AggregateRow>, sqlx::Error> {
+        let mut query_builder = QueryBuilder::new("SELECT date_bin(");
+        query_builder.push_bind(filter.bucket.interval());
+        query_builder.push(r#", timestamp, TIMESTAMPTZ 'epoch') AS bucket, device_name, count(*) AS count,
+    (avg(raw_reading) / 1000)::real AS temperature_avg,
+    (min(raw_reading) / 1000.0)::real AS temperature_min,
+    (max(raw_reading) / 1000.0)::real AS temperature_max
+FROM records.ds18b20"#);
+        push_aggregate_filter(&mut query_builder, filter, viewer_id, "bucket, device_name");
+
+        let aggregates = query_builder.build_query_as::<Ds18b20Aggregate>()
+            .fetch_all(&self.db_pool)
+            .await?;
+
+        Ok(aggregates.into_iter().map(AggregateRow::from).collect())
     }
 
     pub(crate) async fn get_ds18b20_record_by_id(
         &self,
         record_id: Uuid,
+        viewer_id: Uuid,
     ) -> Result<Option<Record>, sqlx::Error> {
-        let record = sqlx::query_as::<_, Ds18b20Record>(
-            r#"SELECT id, device_name, raw_reading, timestamp FROM records.ds18b20 WHERE id = $1"#,
-        )
+        let record = sqlx::query_as::<_, Ds18b20Record>(&format!(
+            r#"SELECT id, device_name, raw_reading, timestamp FROM records.ds18b20 WHERE id = $1 AND owner_id IN ({})"#,
+            visible_owners(2),
+        ))
         .bind(record_id)
+        .bind(viewer_id)
         .fetch_optional(&self.db_pool)
         .await?;
 
@@ -256,19 +577,559 @@ impl Repository {
         }
     }
 
-    pub(crate) async fn get_all_ds18b20_records(&self) -> Result<Vec<Record>, sqlx::Error> {
-        let records = sqlx::query_as::<_, Ds18b20Record>(
-            r#"SELECT id, device_name, raw_reading, timestamp FROM records.ds18b20"#,
-        )
+    pub(crate) async fn get_all_ds18b20_records(&self, viewer_id: Uuid) -> Result<Vec<Record>, sqlx::Error> {
+        let records = sqlx::query_as::<_, Ds18b20Record>(&format!(
+            r#"SELECT id, device_name, raw_reading, timestamp FROM records.ds18b20 WHERE owner_id IN ({})"#,
+            visible_owners(1),
+        ))
+        .bind(viewer_id)
         .fetch_all(&self.db_pool)
         .await?;
         let records: Vec<Record> = records.into_iter().map(Record::from).collect();
         Ok(records)
     }
 
+    /// Every DS18B20 record the viewer may read, along with the name of the device registered
+    /// for it by the owner of the record, if any.
+    pub(crate) async fn get_all_named_ds18b20_records(
+        &self,
+        viewer_id: Uuid,
+    ) -> Result<Vec<(Record, Option<String>)>, sqlx::Error> {
+        let records = sqlx::query_as::<_, NamedDs18b20Record>(&format!(
+            r#"SELECT records.id, records.device_name, records.raw_reading, records.timestamp, devices.name AS device
+FROM records.ds18b20 records
+    LEFT JOIN records.devices devices
+        ON devices.owner_id = records.owner_id AND devices.sensor = 'ds18b20' AND devices.serial = records.device_name
+WHERE records.owner_id IN ({})"#,
+            visible_owners(1),
+        ))
+        .bind(viewer_id)
+        .fetch_all(&self.db_pool)
+        .await?;
+        Ok(records.into_iter().map(|named| (Record::from(named.record), named.device)).collect())
+    }
+
+    /// The devices of the users whose records the viewer may read.
+    pub(crate) async fn list_devices(&self, viewer_id: Uuid) -> Result<Vec<Device>, sqlx::Error> {
+        sqlx::query_as::<_, Device>(&format!(
+            r#"SELECT id, sensor, serial, api_key_id, name, location, description FROM records.devices WHERE owner_id IN ({}) ORDER BY name, id"#,
+            visible_owners(1),
+        ))
+        .bind(viewer_id)
+        .fetch_all(&self.db_pool)
+        .await
+    }
+
+    /// When each source of records the viewer may read was last seen, along with registered
+    /// devices never seen. The sources seen the longest time ago come first.
+    pub(crate) async fn list_device_health(&self, viewer_id: Uuid) -> Result<Vec<DeviceHealth>, sqlx::Error> {
+        sqlx::query_as::<_, DeviceHealth>(&format!(
+            r#"SELECT health.sensor, health.device_name, health.api_key_id, api_keys.name AS api_key_name,
+    health.device_id, health.name, health.last_seen
+FROM (
+    SELECT CASE WHEN last_seen.device_name IS NULL THEN 'bme280' ELSE 'ds18b20' END AS sensor,
+        last_seen.device_name, last_seen.api_key_id, devices.id AS device_id, devices.name, last_seen.last_seen
+    FROM records.last_seen
+    LEFT JOIN records.devices
+        ON devices.owner_id = last_seen.owner_id
+        AND ((devices.sensor = 'ds18b20' AND devices.serial = last_seen.device_name)
+            OR (devices.sensor = 'bme280' AND devices.api_key_id = last_seen.api_key_id))
+    WHERE last_seen.owner_id IN ({visible_owners})
+    UNION ALL
+    SELECT devices.sensor, CASE WHEN devices.sensor = 'ds18b20' THEN devices.serial END,
+        devices.api_key_id, devices.id, devices.name, NULL
+    FROM records.devices
+    WHERE devices.owner_id IN ({visible_owners}) AND NOT EXISTS (
+        SELECT FROM records.last_seen
+        WHERE last_seen.owner_id = devices.owner_id
+            AND ((devices.sensor = 'ds18b20' AND last_seen.device_name = devices.serial)
+                OR (devices.sensor = 'bme280' AND last_seen.api_key_id = devices.api_key_id))
+    )
+) AS health
+LEFT JOIN auth.api_keys ON api_keys.id = health.api_key_id
+ORDER BY health.last_seen NULLS FIRST, health.name, health.device_name"#,
+            visible_owners = visible_owners(1),
+        ))
+        .bind(viewer_id)
+        .fetch_all(&self.db_pool)
+        .await
+    }
+
+    /// The device, if it belongs to a user whose records the viewer may read.
+    pub(crate) async fn get_device_by_id(&self, device_id: Uuid, viewer_id: Uuid) -> Result<Option<Device>, sqlx::Error> {
+        sqlx::query_as::<_, Device>(&format!(
+            r#"SELECT id, sensor, serial, api_key_id, name, location, description FROM records.devices WHERE id = $1 AND owner_id IN ({})"#,
+            visible_owners(2),
+        ))
+        .bind(device_id)
+        .bind(viewer_id)
+        .fetch_optional(&self.db_pool)
+        .await
+    }
+
+    /// The device, if it belongs to the owner, who may change it.
+    pub(crate) async fn get_owned_device(&self, owner_id: Uuid, device_id: Uuid) -> Result<Option<Device>, sqlx::Error> {
+        sqlx::query_as::<_, Device>(
+            r#"SELECT id, sensor, serial, api_key_id, name, location, description FROM records.devices WHERE id = $1 AND owner_id = $2"#,
+        )
+        .bind(device_id)
+        .bind(owner_id)
+        .fetch_optional(&self.db_pool)
+        .await
+    }
+
+    /// Registers a device of the owner. A BME280 station must be linked by an API key of the
+    /// owner.
+    pub(crate) async fn create_device(&self, owner_id: Uuid, device: &Device) -> Result<(), sqlx::Error> {
+        let details = &device.details;
+        sqlx::query(
+            r#"INSERT INTO records.devices (id, sensor, serial, api_key_id, name, location, description, owner_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
+        )
+        .bind(device.id)
+        .bind(details.sensor)
+        .bind(&details.serial)
+        .bind(details.api_key_id)
+        .bind(&details.name)
+        .bind(&details.location)
+        .bind(&details.description)
+        .bind(owner_id)
+        .execute(&self.db_pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Replaces the details of a device of the owner. Returns whether the owner has the device.
+    pub(crate) async fn update_device(&self, owner_id: Uuid, device: &Device) -> Result<bool, sqlx::Error> {
+        let details = &device.details;
+        let result = sqlx::query(
+            r#"UPDATE records.devices SET sensor = $2, serial = $3, api_key_id = $4, name = $5, location = $6, description = $7 WHERE id = $1 AND owner_id = $8"#,
+        )
+        .bind(device.id)
+        .bind(details.sensor)
+        .bind(&details.serial)
+        .bind(details.api_key_id)
+        .bind(&details.name)
+        .bind(&details.location)
+        .bind(&details.description)
+        .bind(owner_id)
+        .execute(&self.db_pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Deletes a device of the owner. Its records are kept. Returns whether the owner had the
+    /// device.
+    pub(crate) async fn delete_device(&self, owner_id: Uuid, device_id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(r#"DELETE FROM records.devices WHERE id = $1 AND owner_id = $2"#)
+            .bind(device_id)
+            .bind(owner_id)
+            .execute(&self.db_pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub(crate) async fn list_calibrations(&self, device_id: Uuid) -> Result<Vec<Calibration>, sqlx::Error> {
+        sqlx::query_as::<_, Calibration>(
+            r#"SELECT id, device_id, quantity, gain, "offset", valid_from, valid_to FROM records.calibrations WHERE device_id = $1 ORDER BY quantity, valid_from"#,
+        )
+        .bind(device_id)
+        .fetch_all(&self.db_pool)
+        .await
+    }
+
+    pub(crate) async fn create_calibration(&self, calibration: &Calibration) -> Result<(), sqlx::Error> {
+        let details = &calibration.details;
+        sqlx::query(
+            r#"INSERT INTO records.calibrations (id, device_id, quantity, gain, "offset", valid_from, valid_to) VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+        )
+        .bind(calibration.id)
+        .bind(calibration.device_id)
+        .bind(details.quantity)
+        .bind(details.gain)
+        .bind(details.offset)
+        .bind(details.valid_from)
+        .bind(details.valid_to)
+        .execute(&self.db_pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Replaces the details of a calibration of a device of the owner. Returns whether the
+    /// calibration exists.
+    pub(crate) async fn update_calibration(&self, owner_id: Uuid, calibration: &Calibration) -> Result<bool, sqlx::Error> {
+        let details = &calibration.details;
+        let result = sqlx::query(
+            r#"UPDATE records.calibrations SET quantity = $3, gain = $4, "offset" = $5, valid_from = $6, valid_to = $7
+WHERE id = $1 AND device_id = $2 AND device_id IN (SELECT id FROM records.devices WHERE owner_id = $8)"#,
+        )
+        .bind(calibration.id)
+        .bind(calibration.device_id)
+        .bind(details.quantity)
+        .bind(details.gain)
+        .bind(details.offset)
+        .bind(details.valid_from)
+        .bind(details.valid_to)
+        .bind(owner_id)
+        .execute(&self.db_pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Deletes a calibration of a device of the owner. Returns whether the calibration existed.
+    pub(crate) async fn delete_calibration(&self, owner_id: Uuid, device_id: Uuid, calibration_id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            r#"DELETE FROM records.calibrations WHERE id = $1 AND device_id = $2 AND device_id IN (SELECT id FROM records.devices WHERE owner_id = $3)"#,
+        )
+            .bind(calibration_id)
+            .bind(device_id)
+            .bind(owner_id)
+            .execute(&self.db_pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The calibrations applicable to the records.
+    ///
+    /// A calibration applies to the records of its device with a timestamp within its validity
+    /// period, as long as the device and the records have the same owner. If several
+    /// calibrations of the same quantity apply, the one most recently taking effect wins.
+    pub(crate) async fn get_record_calibrations(&self, records: &[Record]) -> Result<RecordCalibrations, sqlx::Error> {
+        if records.is_empty() {
+            return Ok(RecordCalibrations::default());
+        }
+
+        let record_ids: Vec<Uuid> = records.iter().map(Record::id).collect();
+        let corrections = sqlx::query_as::<_, Correction>(
+            r#"
+SELECT DISTINCT ON (records.id, calibrations.quantity)
+    records.id AS record_id, calibrations.quantity, calibrations.gain, calibrations."offset"
+FROM (
+    SELECT 'bme280' AS sensor, id, timestamp, owner_id, api_key_id, NULL::text AS device_name FROM records.bme280 WHERE id = ANY($1)
+    UNION ALL
+    SELECT 'ds18b20' AS sensor, id, timestamp, owner_id, NULL::uuid, device_name FROM records.ds18b20 WHERE id = ANY($1)
+) AS records
+    JOIN records.devices devices
+        ON devices.owner_id = records.owner_id
+        AND devices.sensor = records.sensor
+        AND (devices.api_key_id = records.api_key_id OR devices.serial = records.device_name)
+    JOIN records.calibrations calibrations
+        ON calibrations.device_id = devices.id
+        AND calibrations.valid_from <= records.timestamp
+        AND (calibrations.valid_to IS NULL OR records.timestamp < calibrations.valid_to)
+ORDER BY records.id, calibrations.quantity, calibrations.valid_from DESC
+"#,
+        )
+        .bind(record_ids)
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        Ok(corrections.into_iter().collect())
+    }
+
+    /// The alert rules of the owner.
+    ///
+    /// Unlike devices, rules are not listed for the users the owner shares their records with,
+    /// as their webhook URLs are often secrets of their own.
+    pub(crate) async fn list_alert_rules(&self, owner_id: Uuid) -> Result<Vec<AlertRule>, sqlx::Error> {
+        sqlx::query_as::<_, AlertRule>(
+            r#"SELECT id, name, device_id, quantity, comparison, threshold, duration_seconds, webhook_url, firing, breached_since FROM records.alert_rules WHERE owner_id = $1 ORDER BY name, id"#,
+        )
+        .bind(owner_id)
+        .fetch_all(&self.db_pool)
+        .await
+    }
+
+    pub(crate) async fn get_alert_rule_by_id(&self, rule_id: Uuid, owner_id: Uuid) -> Result<Option<AlertRule>, sqlx::Error> {
+        sqlx::query_as::<_, AlertRule>(
+            r#"SELECT id, name, device_id, quantity, comparison, threshold, duration_seconds, webhook_url, firing, breached_since FROM records.alert_rules WHERE id = $1 AND owner_id = $2"#,
+        )
+        .bind(rule_id)
+        .bind(owner_id)
+        .fetch_optional(&self.db_pool)
+        .await
+    }
+
+    /// Creates an alert rule of the owner, on a device of the owner.
+    pub(crate) async fn create_alert_rule(&self, owner_id: Uuid, rule: &AlertRule) -> Result<(), sqlx::Error> {
+        let details = &rule.details;
+        sqlx::query(
+            r#"INSERT INTO records.alert_rules (id, name, device_id, quantity, comparison, threshold, duration_seconds, webhook_url, owner_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
+        )
+        .bind(rule.id)
+        .bind(&details.name)
+        .bind(details.device_id)
+        .bind(details.quantity)
+        .bind(details.comparison)
+        .bind(details.threshold)
+        .bind(details.duration_seconds)
+        .bind(&details.webhook_url)
+        .bind(owner_id)
+        .execute(&self.db_pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Replaces the details of an alert rule of the owner and resets its state, without
+    /// resolving it. The device must be a device of the owner. Returns whether the owner has the
+    /// rule.
+    pub(crate) async fn update_alert_rule(&self, owner_id: Uuid, rule: &AlertRule) -> Result<bool, sqlx::Error> {
+        let details = &rule.details;
+        let result = sqlx::query(
+            r#"UPDATE records.alert_rules SET name = $2, device_id = $3, quantity = $4, comparison = $5, threshold = $6, duration_seconds = $7, webhook_url = $8, firing = false, breached_since = NULL WHERE id = $1 AND owner_id = $9"#,
+        )
+        .bind(rule.id)
+        .bind(&details.name)
+        .bind(details.device_id)
+        .bind(details.quantity)
+        .bind(details.comparison)
+        .bind(details.threshold)
+        .bind(details.duration_seconds)
+        .bind(&details.webhook_url)
+        .bind(owner_id)
+        .execute(&self.db_pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Deletes an alert rule of the owner along with its history. Returns whether the owner had
+    /// the rule.
+    pub(crate) async fn delete_alert_rule(&self, owner_id: Uuid, rule_id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(r#"DELETE FROM records.alert_rules WHERE id = $1 AND owner_id = $2"#)
+            .bind(rule_id)
+            .bind(owner_id)
+            .execute(&self.db_pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The latest alert events of the rules of the owner, newest first.
+    pub(crate) async fn list_alert_history(&self, owner_id: Uuid, limit: u32) -> Result<Vec<AlertHistoryEntry>, sqlx::Error> {
+        sqlx::query_as::<_, AlertHistoryEntry>(
+            r#"SELECT events.id, events.rule_id, events.kind, events.value, events.record_id, events.timestamp,
+    rules.name AS rule_name, devices.name AS device_name
+FROM records.alert_events events
+    JOIN records.alert_rules rules ON rules.id = events.rule_id
+    JOIN records.devices devices ON devices.id = rules.device_id
+WHERE rules.owner_id = $1
+ORDER BY events.timestamp DESC, events.id
+LIMIT $2"#,
+        )
+        .bind(owner_id)
+        .bind(i64::from(limit))
+        .fetch_all(&self.db_pool)
+        .await
+    }
+
+    /// Updates the state of the alert rules of the device of a committed record, and returns
+    /// the rules which fired or resolved along with their events. Only the rules of the owner of
+    /// the record apply.
+    ///
+    /// The rules are locked while they are evaluated, so records of the same device arriving at
+    /// the same time are evaluated one after the other.
+    pub(crate) async fn observe_alert_rules(
+        &self,
+        record: &Record,
+        owner_id: Uuid,
+        api_key_id: Uuid,
+        calibrations: &RecordCalibrations,
+    ) -> Result<Vec<(AlertRule, AlertEvent)>, sqlx::Error> {
+        let mut query_builder = QueryBuilder::<Postgres>::new(
+            r#"SELECT rules.id, rules.name, rules.device_id, rules.quantity, rules.comparison, rules.threshold, rules.duration_seconds, rules.webhook_url, rules.firing, rules.breached_since
+FROM records.alert_rules rules JOIN records.devices devices ON devices.id = rules.device_id
+WHERE rules.owner_id = "#,
+        );
+        query_builder.push_bind(owner_id);
+        match record.reading() {
+            Reading::BME280(_) => {
+                query_builder.push(" AND devices.sensor = 'bme280' AND devices.api_key_id = ");
+                query_builder.push_bind(api_key_id);
+            }
+            Reading::DS18B20(reading) => {
+                query_builder.push(" AND devices.sensor = 'ds18b20' AND devices.serial = ");
+                query_builder.push_bind(reading.device_name().to_string());
+            }
+        }
+        query_builder.push(" ORDER BY rules.id FOR UPDATE OF rules");
+
+        let mut transaction = self.db_pool.begin().await?;
+        let rules = query_builder.build_query_as::<AlertRule>()
+            .fetch_all(&mut *transaction)
+            .await?;
+
+        let mut events = Vec::new();
+        for mut rule in rules {
+            let Some(value) = calibrations.value(record, rule.details.quantity) else {
+                continue;
+            };
+            let kind = rule.observe(value, record.timestamp());
+
+            sqlx::query(r#"UPDATE records.alert_rules SET firing = $2, breached_since = $3 WHERE id = $1"#)
+                .bind(rule.id)
+                .bind(rule.firing)
+                .bind(rule.breached_since)
+                .execute(&mut *transaction)
+                .await?;
+
+            if let Some(kind) = kind {
+                let event = AlertEvent::new(&rule, kind, value, record);
+                sqlx::query(
+                    r#"INSERT INTO records.alert_events (id, rule_id, kind, value, record_id, timestamp) VALUES ($1, $2, $3, $4, $5, $6)"#,
+                )
+                .bind(event.id)
+                .bind(event.rule_id)
+                .bind(event.kind)
+                .bind(event.value)
+                .bind(event.record_id)
+                .bind(event.timestamp)
+                .execute(&mut *transaction)
+                .await?;
+                events.push((rule, event));
+            }
+        }
+
+        transaction.commit().await?;
+
+        Ok(events)
+    }
+
+    pub(crate) async fn list_webhooks(&self, owner_id: Uuid) -> Result<Vec<Webhook>, sqlx::Error> {
+        sqlx::query_as::<_, Webhook>(
+            r#"SELECT id, owner_id, url, secret, sensor, device_name FROM records.webhooks WHERE owner_id = $1 ORDER BY url, id"#,
+        )
+        .bind(owner_id)
+        .fetch_all(&self.db_pool)
+        .await
+    }
+
+    /// The webhooks of the users who may read the records of the owner.
+    pub(crate) async fn list_webhooks_receiving(&self, owner_id: Uuid) -> Result<Vec<Webhook>, sqlx::Error> {
+        sqlx::query_as::<_, Webhook>(
+            r#"SELECT id, owner_id, url, secret, sensor, device_name FROM records.webhooks
+WHERE owner_id = $1 OR owner_id IN (SELECT grantee_id FROM auth.record_shares WHERE owner_id = $1)"#,
+        )
+        .bind(owner_id)
+        .fetch_all(&self.db_pool)
+        .await
+    }
+
+    pub(crate) async fn create_webhook(&self, webhook: &Webhook) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"INSERT INTO records.webhooks (id, owner_id, url, secret, sensor, device_name) VALUES ($1, $2, $3, $4, $5, $6)"#,
+        )
+        .bind(webhook.id)
+        .bind(webhook.owner_id)
+        .bind(&webhook.url)
+        .bind(&webhook.secret)
+        .bind(&webhook.filter.sensor)
+        .bind(&webhook.filter.device_name)
+        .execute(&self.db_pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Deletes a webhook of a user along with its delivery log. Returns whether it existed.
+    pub(crate) async fn delete_webhook(&self, owner_id: Uuid, webhook_id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(r#"DELETE FROM records.webhooks WHERE id = $1 AND owner_id = $2"#)
+            .bind(webhook_id)
+            .bind(owner_id)
+            .execute(&self.db_pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub(crate) async fn create_webhook_delivery(&self, delivery: &WebhookDelivery) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"INSERT INTO records.webhook_deliveries (id, delivery_id, webhook_id, record_id, attempt, status_code, error, succeeded, attempted_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
+        )
+        .bind(delivery.id)
+        .bind(delivery.delivery_id)
+        .bind(delivery.webhook_id)
+        .bind(delivery.record_id)
+        .bind(delivery.attempt)
+        .bind(delivery.status_code)
+        .bind(&delivery.error)
+        .bind(delivery.succeeded)
+        .bind(delivery.attempted_at)
+        .execute(&self.db_pool)
+        .await?;
+        Ok(())
+    }
+
+    /// The latest delivery attempts to the webhooks of a user, newest first.
+    pub(crate) async fn list_webhook_deliveries(&self, owner_id: Uuid, limit: u32) -> Result<Vec<WebhookDelivery>, sqlx::Error> {
+        sqlx::query_as::<_, WebhookDelivery>(
+            r#"SELECT deliveries.id, deliveries.delivery_id, deliveries.webhook_id, deliveries.record_id, deliveries.attempt,
+    deliveries.status_code, deliveries.error, deliveries.succeeded, deliveries.attempted_at
+FROM records.webhook_deliveries deliveries
+    JOIN records.webhooks webhooks ON webhooks.id = deliveries.webhook_id
+WHERE webhooks.owner_id = $1
+ORDER BY deliveries.attempted_at DESC, deliveries.id
+LIMIT $2"#,
+        )
+        .bind(owner_id)
+        .bind(i64::from(limit))
+        .fetch_all(&self.db_pool)
+        .await
+    }
+
+    /// The shares the user has granted or been granted.
+    pub(crate) async fn list_shares(&self, user_id: Uuid) -> Result<Vec<RecordShare>, sqlx::Error> {
+        sqlx::query_as::<_, RecordShare>(
+            r#"SELECT shares.owner_id, owners.username AS owner, shares.grantee_id, grantees.username AS grantee
+FROM auth.record_shares shares
+    JOIN auth.users owners ON shares.owner_id = owners.id
+    JOIN auth.users grantees ON shares.grantee_id = grantees.id
+WHERE shares.owner_id = $1 OR shares.grantee_id = $1
+ORDER BY owners.username, grantees.username"#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.db_pool)
+        .await
+    }
+
+    /// Shares the records of the owner with the user of the given name. Returns whether there
+    /// is such a user. Sharing again with the same user changes nothing.
+    pub(crate) async fn create_share(&self, owner_id: Uuid, grantee: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            r#"INSERT INTO auth.record_shares (owner_id, grantee_id)
+SELECT $1, id FROM auth.users WHERE username = $2
+ON CONFLICT DO NOTHING"#,
+        )
+        .bind(owner_id)
+        .bind(grantee)
+        .execute(&self.db_pool)
+        .await?;
+        if result.rows_affected() > 0 {
+            return Ok(true);
+        }
+
+        let exists = sqlx::query_scalar::<_, bool>(r#"SELECT EXISTS (SELECT 1 FROM auth.users WHERE username = $1)"#)
+            .bind(grantee)
+            .fetch_one(&self.db_pool)
+            .await?;
+        Ok(exists)
+    }
+
+    pub(crate) async fn delete_share(&self, owner_id: Uuid, grantee_id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(r#"DELETE FROM auth.record_shares WHERE owner_id = $1 AND grantee_id = $2"#)
+            .bind(owner_id)
+            .bind(grantee_id)
+            .execute(&self.db_pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The users whose records the viewer may read, including the viewer.
+    pub(crate) async fn list_visible_owners(&self, viewer_id: Uuid) -> Result<Vec<Uuid>, sqlx::Error> {
+        sqlx::query_scalar::<_, Uuid>(&visible_owners(1))
+            .bind(viewer_id)
+            .fetch_all(&self.db_pool)
+            .await
+    }
+
     pub(crate) async fn get_user_by_username(&self, username: &str) -> Result<User, sqlx::Error> {
         let user = sqlx::query_as::<_, User>(
-            r#"SELECT id, username, password FROM auth.users WHERE username = $1"#,
+            r#"SELECT id, username, password, role, disabled, pending FROM auth.users WHERE username = $1"#,
         )
         .bind(username)
         .fetch_one(&self.db_pool)
@@ -277,71 +1138,572 @@ impl Repository {
         Ok(user)
     }
 
+    pub(crate) async fn get_user_by_id(&self, user_id: Uuid) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as::<_, User>(r#"SELECT id, username, password, role, disabled, pending FROM auth.users WHERE id = $1"#)
+            .bind(user_id)
+            .fetch_optional(&self.db_pool)
+            .await
+    }
+
     pub(crate) async fn create_user(
         &self,
         username: &str,
         password: &str,
+        role: Role,
     ) -> Result<User, sqlx::Error> {
-        let user = User::new(username.into(), password.into()).unwrap();
-        sqlx::query(r#"INSERT INTO auth.users (id, username, password) VALUES ($1, $2, $3);"#)
+        let user = User::new(username.into(), password.into(), role).unwrap();
+        insert_user(&mut *self.db_pool.acquire().await?, &user).await?;
+        Ok(user)
+    }
+
+    /// Creates a member who cannot log in until approved by an admin.
+    pub(crate) async fn create_pending_user(&self, username: &str, password: &str) -> Result<User, sqlx::Error> {
+        let user = User::new(username.into(), password.into(), Role::Member).unwrap().into_pending();
+        insert_user(&mut *self.db_pool.acquire().await?, &user).await?;
+        Ok(user)
+    }
+
+    /// Creates the very first user, as an admin. Returns `None` if there already is a user.
+    pub(crate) async fn create_first_user(&self, username: &str, password: &str) -> Result<Option<User>, sqlx::Error> {
+        let mut transaction = self.db_pool.begin().await?;
+        // Keeps concurrent registrations from both becoming the first user.
+        sqlx::query("LOCK TABLE auth.users IN SHARE ROW EXCLUSIVE MODE")
+            .execute(&mut *transaction)
+            .await?;
+
+        let any_user = sqlx::query_scalar::<_, bool>(r#"SELECT EXISTS (SELECT 1 FROM auth.users)"#)
+            .fetch_one(&mut *transaction)
+            .await?;
+        if any_user {
+            return Ok(None);
+        }
+
+        let user = User::new(username.into(), password.into(), Role::Admin).unwrap();
+        insert_user(&mut transaction, &user).await?;
+        transaction.commit().await?;
+        Ok(Some(user))
+    }
+
+    /// Creates a user with the role of the unused invite with the code hash, and marks the
+    /// invite used. Returns `None` if there is no such invite.
+    pub(crate) async fn create_user_with_invite(
+        &self,
+        username: &str,
+        password: &str,
+        code_hash: &str,
+    ) -> Result<Option<User>, sqlx::Error> {
+        let mut transaction = self.db_pool.begin().await?;
+        let role = sqlx::query_scalar::<_, Role>(
+            r#"SELECT role FROM auth.invites WHERE code_hash = $1 AND used_at IS NULL FOR UPDATE"#,
+        )
+        .bind(code_hash)
+        .fetch_optional(&mut *transaction)
+        .await?;
+        let Some(role) = role else {
+            return Ok(None);
+        };
+
+        let user = User::new(username.into(), password.into(), role).unwrap();
+        insert_user(&mut transaction, &user).await?;
+        sqlx::query(r#"UPDATE auth.invites SET used_by = $1, used_at = now() WHERE code_hash = $2"#)
             .bind(user.id())
-            .bind(user.username())
-            .bind(user.hashed_password())
+            .bind(code_hash)
+            .execute(&mut *transaction)
+            .await?;
+
+        transaction.commit().await?;
+        Ok(Some(user))
+    }
+
+    /// Every user, those awaiting approval first.
+    pub(crate) async fn list_users(&self) -> Result<Vec<UserSummary>, sqlx::Error> {
+        sqlx::query_as::<_, UserSummary>(
+            r#"SELECT id, username, role, disabled, pending FROM auth.users ORDER BY pending DESC, username"#,
+        )
+            .fetch_all(&self.db_pool)
+            .await
+    }
+
+    pub(crate) async fn set_user_role(&self, user_id: Uuid, role: Role) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(r#"UPDATE auth.users SET role = $1 WHERE id = $2"#)
+            .bind(role)
+            .bind(user_id)
             .execute(&self.db_pool)
             .await?;
+        Ok(result.rows_affected() > 0)
+    }
 
-        Ok(user)
+    pub(crate) async fn set_user_disabled(&self, user_id: Uuid, disabled: bool) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(r#"UPDATE auth.users SET disabled = $1 WHERE id = $2"#)
+            .bind(disabled)
+            .bind(user_id)
+            .execute(&self.db_pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The number of admins able to log in.
+    pub(crate) async fn count_active_admins(&self) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar::<_, i64>(
+            r#"SELECT count(*) FROM auth.users WHERE role = 'admin' AND NOT disabled AND NOT pending"#,
+        )
+        .fetch_one(&self.db_pool)
+        .await
+    }
+
+    pub(crate) async fn set_user_password(&self, user_id: Uuid, hashed_password: &str) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"UPDATE auth.users SET password = $1 WHERE id = $2"#)
+            .bind(hashed_password)
+            .bind(user_id)
+            .execute(&self.db_pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Deletes a user along with everything they own, such as their records and API keys.
+    pub(crate) async fn delete_user(&self, user_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"DELETE FROM auth.users WHERE id = $1"#)
+            .bind(user_id)
+            .execute(&self.db_pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Creates a password reset for the user, valid for a day. Returns when it expires, or `None`
+    /// if there is no such user.
+    pub(crate) async fn create_password_reset(
+        &self,
+        user_id: Uuid,
+        created_by: Uuid,
+        token_hash: &str,
+    ) -> Result<Option<chrono::DateTime<chrono::Utc>>, sqlx::Error> {
+        sqlx::query_scalar::<_, chrono::DateTime<chrono::Utc>>(
+            r#"
+INSERT INTO auth.password_resets (id, user_id, token_hash, created_by, expires_at)
+SELECT $1, id, $2, $3, now() + interval '1 day' FROM auth.users WHERE id = $4
+RETURNING expires_at
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(token_hash)
+        .bind(created_by)
+        .bind(user_id)
+        .fetch_optional(&self.db_pool)
+        .await
+    }
+
+    /// Sets the password of the user with an unexpired password reset with the token hash, and
+    /// removes every password reset of the user. Returns `false` if there is no such reset.
+    pub(crate) async fn reset_password(&self, token_hash: &str, hashed_password: &str) -> Result<bool, sqlx::Error> {
+        let mut transaction = self.db_pool.begin().await?;
+        let user_id = sqlx::query_scalar::<_, Uuid>(
+            r#"SELECT user_id FROM auth.password_resets WHERE token_hash = $1 AND expires_at > now() FOR UPDATE"#,
+        )
+        .bind(token_hash)
+        .fetch_optional(&mut *transaction)
+        .await?;
+        let Some(user_id) = user_id else {
+            return Ok(false);
+        };
+
+        sqlx::query(r#"UPDATE auth.users SET password = $1 WHERE id = $2"#)
+            .bind(hashed_password)
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query(r#"DELETE FROM auth.password_resets WHERE user_id = $1"#)
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?;
+
+        transaction.commit().await?;
+        Ok(true)
+    }
+
+    /// The base32 secret of the TOTP second factor of the user, if they have enrolled one.
+    pub(crate) async fn get_totp_secret(&self, user_id: Uuid) -> Result<Option<String>, sqlx::Error> {
+        sqlx::query_scalar::<_, String>(r#"SELECT secret FROM auth.totp WHERE user_id = $1"#)
+            .bind(user_id)
+            .fetch_optional(&self.db_pool)
+            .await
     }
 
-    pub(crate) async fn create_api_key(&self, api_key: &ApiKey) -> Result<(), sqlx::Error> {
-        let owner = self.get_user_by_username(api_key.owner()).await?;
+    /// Enrols a TOTP second factor for the user, replacing any earlier one along with its
+    /// recovery codes.
+    pub(crate) async fn enable_totp(
+        &self,
+        user_id: Uuid,
+        secret: &str,
+        recovery_code_hashes: &[String],
+    ) -> Result<(), sqlx::Error> {
+        let mut transaction = self.db_pool.begin().await?;
         sqlx::query(
-            r#"INSERT INTO auth.api_keys (id, name, owner_id, token) VALUES ($1, $2, $3, $4);"#,
+            r#"
+INSERT INTO auth.totp (user_id, secret) VALUES ($1, $2)
+ON CONFLICT (user_id) DO UPDATE SET secret = EXCLUDED.secret, last_step = NULL, failed_attempts = 0, locked_until = NULL, enabled_at = now()
+            "#,
+        )
+        .bind(user_id)
+        .bind(secret)
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query(r#"DELETE FROM auth.recovery_codes WHERE user_id = $1"#)
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query(r#"INSERT INTO auth.recovery_codes (user_id, code_hash) SELECT $1, UNNEST($2::text[])"#)
+            .bind(user_id)
+            .bind(recovery_code_hashes)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await
+    }
+
+    pub(crate) async fn disable_totp(&self, user_id: Uuid) -> Result<(), sqlx::Error> {
+        let mut transaction = self.db_pool.begin().await?;
+        sqlx::query(r#"DELETE FROM auth.totp WHERE user_id = $1"#)
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query(r#"DELETE FROM auth.recovery_codes WHERE user_id = $1"#)
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await
+    }
+
+    /// Records that the user logged in with the TOTP code of the time step. Returns `false` if a
+    /// code of the same or a later time step has already been used, so the code is a replay.
+    pub(crate) async fn use_totp_step(&self, user_id: Uuid, step: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            r#"UPDATE auth.totp SET last_step = $2 WHERE user_id = $1 AND (last_step IS NULL OR last_step < $2)"#,
+        )
+        .bind(user_id)
+        .bind(step)
+        .execute(&self.db_pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Counts an attempt of the user to enter a code, before the code is checked. The second
+    /// factor is locked for `lockout_seconds` once `max_attempts` codes have been entered since
+    /// the last accepted code. Returns the number of the attempt, or `None` if the second factor
+    /// is locked, in which case no code may be checked.
+    ///
+    /// The attempt is counted and checked against the lockout in a single statement, so that
+    /// concurrent attempts cannot all slip in before any of them is counted.
+    pub(crate) async fn reserve_totp_attempt(
+        &self,
+        user_id: Uuid,
+        max_attempts: i32,
+        lockout_seconds: f64,
+    ) -> Result<Option<i32>, sqlx::Error> {
+        // A lockout which has run out starts the count afresh.
+        sqlx::query_scalar::<_, i32>(
+            r#"
+UPDATE auth.totp SET
+    failed_attempts = CASE WHEN locked_until IS NULL THEN failed_attempts + 1 ELSE 1 END,
+    locked_until = CASE
+        WHEN (CASE WHEN locked_until IS NULL THEN failed_attempts + 1 ELSE 1 END) >= $2 THEN now() + make_interval(secs => $3)
+    END
+WHERE user_id = $1 AND (locked_until IS NULL OR locked_until <= now())
+RETURNING failed_attempts
+            "#,
+        )
+        .bind(user_id)
+        .bind(max_attempts)
+        .bind(lockout_seconds)
+        .fetch_optional(&self.db_pool)
+        .await
+    }
+
+    /// Forgets the codes entered by the user, and lifts any lockout they caused, once a right
+    /// one was entered.
+    pub(crate) async fn reset_totp_attempts(&self, user_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"UPDATE auth.totp SET failed_attempts = 0, locked_until = NULL WHERE user_id = $1"#)
+            .bind(user_id)
+            .execute(&self.db_pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Uses up a recovery code of the user. Returns `false` if there is no such unused code.
+    pub(crate) async fn use_recovery_code(&self, user_id: Uuid, code_hash: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(r#"DELETE FROM auth.recovery_codes WHERE user_id = $1 AND code_hash = $2"#)
+            .bind(user_id)
+            .bind(code_hash)
+            .execute(&self.db_pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub(crate) async fn count_recovery_codes(&self, user_id: Uuid) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar::<_, i64>(r#"SELECT count(*) FROM auth.recovery_codes WHERE user_id = $1"#)
+            .bind(user_id)
+            .fetch_one(&self.db_pool)
+            .await
+    }
+
+    pub(crate) async fn approve_user(&self, user_id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(r#"UPDATE auth.users SET pending = false WHERE id = $1 AND pending"#)
+            .bind(user_id)
+            .execute(&self.db_pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Deletes a user awaiting approval. Users who have been approved are never deleted this way.
+    pub(crate) async fn reject_user(&self, user_id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(r#"DELETE FROM auth.users WHERE id = $1 AND pending"#)
+            .bind(user_id)
+            .execute(&self.db_pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub(crate) async fn create_invite(&self, invite: &Invite, created_by: Uuid, code_hash: &str) -> Result<(), sqlx::Error> {
+        sqlx::query(r#"INSERT INTO auth.invites (id, code_hash, role, created_by, created_at) VALUES ($1, $2, $3, $4, $5)"#)
+            .bind(invite.id)
+            .bind(code_hash)
+            .bind(invite.role)
+            .bind(created_by)
+            .bind(invite.created_at)
+            .execute(&self.db_pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Every invite, the most recently created first.
+    pub(crate) async fn list_invites(&self) -> Result<Vec<Invite>, sqlx::Error> {
+        sqlx::query_as::<_, Invite>(
+            r#"SELECT invites.id, invites.role, creators.username AS created_by, invites.created_at,
+    users.username AS used_by, invites.used_at
+FROM auth.invites invites
+    LEFT JOIN auth.users creators ON invites.created_by = creators.id
+    LEFT JOIN auth.users users ON invites.used_by = users.id
+ORDER BY invites.created_at DESC"#,
+        )
+        .fetch_all(&self.db_pool)
+        .await
+    }
+
+    pub(crate) async fn delete_invite(&self, invite_id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(r#"DELETE FROM auth.invites WHERE id = $1"#)
+            .bind(invite_id)
+            .execute(&self.db_pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub(crate) async fn create_api_key(&self, api_key: &ApiKey, token_hash: &str) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"INSERT INTO auth.api_keys (id, name, owner_id, token_hash, token_prefix, scopes, expires_at) VALUES ($1, $2, $3, $4, $5, $6, $7);"#,
         )
         .bind(api_key.id())
         .bind(api_key.name())
-        .bind(owner.id())
-        .bind(api_key.token())
+        .bind(api_key.owner_id())
+        .bind(token_hash)
+        .bind(api_key.token_prefix())
+        .bind(api_key.scopes())
+        .bind(api_key.expires_at())
         .execute(&self.db_pool)
         .await?;
         Ok(())
     }
 
-    pub(crate) async fn get_api_key_by_token(
+    pub(crate) async fn get_api_key_by_token_hash(
         &self,
-        token_value: &str,
+        token_hash: &str,
     ) -> Result<ApiKey, sqlx::Error> {
         let token = sqlx::query_as::<_, ApiKey>(
             r#"
 SELECT
-    keys.id, keys.name, users.username as owner, token
+    keys.id, keys.name, keys.owner_id, users.username as owner, token_prefix, scopes, expires_at, revoked_at
 FROM
     auth.api_keys keys
     JOIN auth.users users ON keys.owner_id = users.id
 WHERE
-    token = $1;
+    token_hash = $1;
 "#,
         )
-        .bind(token_value)
+        .bind(token_hash)
         .fetch_one(&self.db_pool)
         .await?;
         Ok(token)
     }
 
-    pub(crate) async fn list_api_keys(&self) -> Result<Vec<ApiKey>, sqlx::Error> {
+    /// The API keys owned by the user.
+    pub(crate) async fn list_api_keys(&self, owner_id: Uuid) -> Result<Vec<ApiKey>, sqlx::Error> {
         let records = sqlx::query_as::<_, ApiKey>(
             r#"
 SELECT
-    keys.id, name, username as owner, token
+    keys.id, name, keys.owner_id, username as owner, token_prefix, scopes, expires_at, revoked_at
 FROM
     auth.api_keys keys
-    JOIN auth.users users ON keys.owner_id = users.id;
+    JOIN auth.users users ON keys.owner_id = users.id
+WHERE
+    keys.owner_id = $1
+ORDER BY
+    name;
             "#,
         )
+        .bind(owner_id)
         .fetch_all(&self.db_pool)
         .await?;
         Ok(records)
     }
+
+    /// The API keys of every user.
+    pub(crate) async fn list_all_api_keys(&self) -> Result<Vec<ApiKey>, sqlx::Error> {
+        sqlx::query_as::<_, ApiKey>(
+            r#"
+SELECT
+    keys.id, name, keys.owner_id, username as owner, token_prefix, scopes, expires_at, revoked_at
+FROM
+    auth.api_keys keys
+    JOIN auth.users users ON keys.owner_id = users.id
+ORDER BY
+    username, name;
+            "#,
+        )
+        .fetch_all(&self.db_pool)
+        .await
+    }
+
+    /// Revokes an API key owned by the user. Returns whether the user owns such a key. Revoking
+    /// a key again keeps the time it was first revoked.
+    pub(crate) async fn revoke_api_key(&self, owner_id: Uuid, api_key_id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            r#"UPDATE auth.api_keys SET revoked_at = COALESCE(revoked_at, now()) WHERE id = $1 AND owner_id = $2"#,
+        )
+        .bind(api_key_id)
+        .bind(owner_id)
+        .execute(&self.db_pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub(crate) async fn delete_api_key(&self, owner_id: Uuid, api_key_id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(r#"DELETE FROM auth.api_keys WHERE id = $1 AND owner_id = $2"#)
+            .bind(api_key_id)
+            .bind(owner_id)
+            .execute(&self.db_pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+/// Appends the conditions, ordering and limit of the filter to a query selecting records.
+///
+/// One record more than the page size is requested, which is how [`Page::from_overfetched`]
+/// tells whether there is a next page. Returns the page size.
+fn push_filter(
+    query_builder: &mut QueryBuilder<'_, Postgres>,
+    source: RecordSource,
+    filter: &RecordFilter,
+    viewer_id: Uuid,
+) -> usize {
+    let limit = limit(filter.limit);
+    push_conditions(query_builder, source, filter, viewer_id);
+    query_builder.push(" LIMIT ").push_bind(limit + 1);
+
+    limit as usize
+}
+
+/// Appends the conditions and ordering of the filter to a query selecting records, leaving out
+/// records the viewer may not read.
+fn push_conditions(
+    query_builder: &mut QueryBuilder<'_, Postgres>,
+    source: RecordSource,
+    filter: &RecordFilter,
+    viewer_id: Uuid,
+) {
+    let order = filter.order.as_sql();
+    let comparison = match filter.order {
+        Order::Asc => ">",
+        Order::Desc => "<",
+    };
+    query_builder.push(" WHERE TRUE ");
+    push_visible_to(query_builder, viewer_id);
+
+    if let Some(from) = filter.from {
+        query_builder.push(" AND timestamp >= ").push_bind(from);
+    }
+
+    if let Some(to) = filter.to {
+        query_builder.push(" AND timestamp < ").push_bind(to);
+    }
+
+    if !filter.device_name.is_empty() {
+        query_builder
+            .push(" AND device_name = ANY(")
+            .push_bind(filter.device_name.clone())
+            .push(")");
+    }
+
+    // Records past the given one in the order of the query, which must itself be readable.
+    if let Some(since_id) = filter.since_id {
+        query_builder.push(format!(" AND (timestamp, id) {comparison} (SELECT timestamp, id FROM "));
+        source.push_table(query_builder);
+        query_builder.push(" AS since WHERE id = ").push_bind(since_id);
+        push_visible_to(query_builder, viewer_id);
+        query_builder.push(" LIMIT 1)");
+    }
+
+    if let Some(cursor) = filter.cursor {
+        query_builder
+            .push(format!(" AND (timestamp, id) {comparison} ("))
+            .push_bind(cursor.timestamp())
+            .push(", ")
+            .push_bind(cursor.id())
+            .push(")");
+    }
+
+    query_builder.push(format!(" ORDER BY timestamp {order}, id {order}"));
+}
+
+/// Appends the conditions of the filter and the grouping to a query aggregating records,
+/// leaving out records the viewer may not read. The result is grouped and ordered by the given
+/// columns.
+fn push_aggregate_filter(
+    query_builder: &mut QueryBuilder<'_, Postgres>,
+    filter: &AggregateFilter,
+    viewer_id: Uuid,
+    group_by: &str,
+) {
+    query_builder.push(" WHERE TRUE ");
+    push_visible_to(query_builder, viewer_id);
+
+    query_builder
+        .push(" AND timestamp >= ")
+        .push_bind(filter.from)
+        .push(" AND timestamp < ")
+        .push_bind(filter.to);
+
+    if !filter.device_name.is_empty() {
+        query_builder
+            .push(" AND device_name = ANY(")
+            .push_bind(filter.device_name.clone())
+            .push(")");
+    }
+
+    query_builder.push(format!(" GROUP BY {group_by} ORDER BY {group_by}"));
+}
+
+/// Appends a condition leaving out records the viewer may not read.
+fn push_visible_to(query_builder: &mut QueryBuilder<'_, Postgres>, viewer_id: Uuid) {
+    query_builder
+        .push(" AND owner_id IN (SELECT ")
+        .push_bind(viewer_id)
+        .push(" UNION ALL SELECT owner_id FROM auth.record_shares WHERE grantee_id = ")
+        .push_bind(viewer_id)
+        .push(")");
+}
+
+/// The users whose records a user may read: the user itself and every user sharing their
+/// records with it. The user is the query parameter with the given number.
+fn visible_owners(parameter: usize) -> String {
+    format!("SELECT ${parameter} UNION ALL SELECT owner_id FROM auth.record_shares WHERE grantee_id = ${parameter}")
 }
 
 fn limit(max_length: Option<u32>) -> i32 {
@@ -351,3 +1713,196 @@ fn limit(max_length: Option<u32>) -> i32 {
     };
     max as i32
 }
+
+/// Inserts the record, owned by the owner of the API key, and announces it on
+/// [`RECORDS_CHANNEL`].
+///
+/// Must be called within a transaction, or the announcement may be sent for a record that is
+/// not stored after all. BME280 records keep the API key they were submitted with, which is what
+/// links them to their device.
+async fn insert_record(
+    connection: &mut PgConnection,
+    record: &Record,
+    api_key: &ApiKey,
+) -> Result<Uuid, sqlx::Error> {
+    let api_key_id = api_key.id();
+    let owner_id = api_key.owner_id();
+    let record_id = record.id();
+    let timestamp = record.timestamp();
+    let reading = record.reading();
+
+    // Claims the id for every sensor, so an id in use by another sensor is a unique violation.
+    sqlx::query(r#"INSERT INTO records.record_ids (id, owner_id) VALUES ($1, $2)"#)
+        .bind(record_id)
+        .bind(owner_id)
+        .execute(&mut *connection)
+        .await?;
+
+    match reading {
+        Reading::BME280(reading) => {
+            let temperature = reading.temperature();
+            let pressure = reading.pressure();
+            let humidity = reading.humidity();
+
+            sqlx::query(r#"INSERT INTO records.bme280 (id, temperature, pressure, humidity, timestamp, api_key_id, owner_id) VALUES ($1, $2, $3, $4, $5, $6, $7)"#)
+                .bind(record_id)
+                .bind(temperature)
+                .bind(pressure)
+                .bind(humidity)
+                .bind(timestamp)
+                .bind(api_key_id)
+                .bind(owner_id)
+                .execute(&mut *connection)
+                .await?;
+
+            sqlx::query(r#"INSERT INTO records.last_seen (api_key_id, last_seen, owner_id) VALUES ($1, $2, $3)
+ON CONFLICT (api_key_id) DO UPDATE SET last_seen = GREATEST(records.last_seen.last_seen, excluded.last_seen)"#)
+                .bind(api_key_id)
+                .bind(timestamp)
+                .bind(owner_id)
+                .execute(&mut *connection)
+                .await?;
+        }
+        Reading::DS18B20(reading) => {
+            let device_name = reading.device_name();
+            let raw_reading = reading.raw_reading();
+
+            sqlx::query(r#"INSERT INTO records.ds18b20 (id, device_name, raw_reading, timestamp, owner_id) VALUES ($1, $2, $3, $4, $5)"#)
+                .bind(record_id)
+                .bind(device_name)
+                .bind(raw_reading)
+                .bind(timestamp)
+                .bind(owner_id)
+                .execute(&mut *connection)
+                .await?;
+
+            sqlx::query(r#"INSERT INTO records.last_seen (device_name, last_seen, owner_id) VALUES ($1, $2, $3)
+ON CONFLICT (owner_id, device_name) DO UPDATE SET last_seen = GREATEST(records.last_seen.last_seen, excluded.last_seen)"#)
+                .bind(device_name)
+                .bind(timestamp)
+                .bind(owner_id)
+                .execute(&mut *connection)
+                .await?;
+        }
+    }
+
+    let notification = RecordNotification { id: record_id, owner_id, sensor: SensorType::of(record) };
+    let payload = serde_json::to_string(&notification)
+        .map_err(|error| sqlx::Error::Encode(error.into()))?;
+    sqlx::query("SELECT pg_notify($1, $2)")
+        .bind(RECORDS_CHANNEL)
+        .bind(payload)
+        .execute(&mut *connection)
+        .await?;
+
+    Ok(record_id)
+}
+
+async fn insert_user(connection: &mut PgConnection, user: &User) -> Result<(), sqlx::Error> {
+    sqlx::query(r#"INSERT INTO auth.users (id, username, password, role, pending) VALUES ($1, $2, $3, $4, $5);"#)
+        .bind(user.id())
+        .bind(user.username())
+        .bind(user.hashed_password())
+        .bind(user.role())
+        .bind(user.pending())
+        .execute(connection)
+        .await?;
+    Ok(())
+}
+
+/// Whether the error is the database rejecting a single record, as opposed to a failure of
+/// the database or the connection itself.
+fn is_constraint_violation(error: &sqlx::Error) -> bool {
+    use sqlx::error::ErrorKind;
+
+    match error {
+        sqlx::Error::Database(db_error) => matches!(
+            db_error.kind(),
+            ErrorKind::UniqueViolation
+                | ErrorKind::ForeignKeyViolation
+                | ErrorKind::NotNullViolation
+                | ErrorKind::CheckViolation
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::authentication::api_key::Scope;
+    use crate::authentication::user::Role;
+    use sqlx::postgres::PgPoolOptions;
+    use std::time::Duration;
+
+    /// A repository on the database at `DATABASE_URL`, with at most the given number of
+    /// connections.
+    pub(crate) async fn repository(max_connections: u32) -> Repository {
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for database tests");
+        let db_pool = PgPoolOptions::new()
+            .max_connections(max_connections)
+            .acquire_timeout(Duration::from_secs(5))
+            .connect(&database_url)
+            .await
+            .unwrap();
+        Repository::new(db_pool)
+    }
+
+    /// A new user, to be deleted along with all of its data once the test is done.
+    pub(crate) async fn new_user(repository: &Repository) -> User {
+        let username = format!("test-{}", Uuid::new_v4());
+        repository.create_user(&username, "password", Role::Member).await.unwrap()
+    }
+
+    pub(crate) async fn new_api_key(repository: &Repository, user: &User, name: &str) -> ApiKey {
+        let (key, token) = ApiKey::new(name, *user.id(), user.username(), vec![Scope::RecordsWrite], None);
+        repository.create_api_key(&key, &token.to_string()).await.unwrap();
+        key
+    }
+
+    fn bme280(seconds: i64, temperature: f32) -> Record {
+        let timestamp = chrono::DateTime::from_timestamp(1772184765 + seconds, 0).unwrap();
+        Record::new(Uuid::new_v4(), timestamp, Reading::BME280(BME280::new(temperature, 100000.0, 45.0)))
+    }
+
+    pub(crate) fn ds18b20(seconds: i64, device_name: &str) -> Record {
+        let timestamp = chrono::DateTime::from_timestamp(1772184765 + seconds, 0).unwrap();
+        Record::new(Uuid::new_v4(), timestamp, Reading::DS18B20(DS18B20::new(device_name.to_string(), 22123)))
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a database at DATABASE_URL"]
+    async fn test_latest_records_of_every_station() {
+        let repository = repository(5).await;
+        let user = new_user(&repository).await;
+        let greenhouse = new_api_key(&repository, &user, "greenhouse").await;
+        let shed = new_api_key(&repository, &user, "shed").await;
+
+        let records = [
+            (bme280(0, 20.0), &greenhouse),
+            (bme280(60, 21.0), &greenhouse),
+            (bme280(0, 10.0), &shed),
+            (bme280(30, 11.0), &shed),
+            (ds18b20(0, "0000003e33d5"), &greenhouse),
+            (ds18b20(90, "0000003e33d5"), &shed),
+        ];
+        for (record, api_key) in &records {
+            repository.commit_record(record, api_key).await.unwrap();
+        }
+
+        // A probe of another owner reporting the same device name is another sensor.
+        let neighbour = new_user(&repository).await;
+        repository.create_share(*neighbour.id(), user.username()).await.unwrap();
+        let neighbour_record = ds18b20(30, "0000003e33d5");
+        repository.commit_record(&neighbour_record, &new_api_key(&repository, &neighbour, "garden").await).await.unwrap();
+
+        let mut latest: Vec<Uuid> = repository.get_latest_records(*user.id()).await.unwrap().iter().map(Record::id).collect();
+        latest.sort();
+        let mut expected = vec![records[1].0.id(), records[3].0.id(), records[5].0.id(), neighbour_record.id()];
+        expected.sort();
+
+        repository.delete_user(*user.id()).await.unwrap();
+        repository.delete_user(*neighbour.id()).await.unwrap();
+        assert_eq!(latest, expected);
+    }
+}
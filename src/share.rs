@@ -0,0 +1,14 @@
+use serde::Serialize;
+use uuid::Uuid;
+
+/// A user letting another user read all of their records.
+///
+/// Records are otherwise only readable by their owner, the owner of the API key that submitted
+/// them.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub(crate) struct RecordShare {
+    pub owner_id: Uuid,
+    pub owner: String,
+    pub grantee_id: Uuid,
+    pub grantee: String,
+}
@@ -1,14 +1,30 @@
+use crate::alert::AlertEvaluator;
+use crate::authentication::registration::RegistrationMode;
+use crate::authentication::token::TokenHasher;
+use crate::feed::RecordFeed;
 use crate::repository::Repository;
+use crate::webhook::WebhookDispatcher;
 
 #[derive(Clone)]
 pub(crate) struct AppState {
     pub repository: Repository,
+    pub record_feed: RecordFeed,
+    pub alert_evaluator: AlertEvaluator,
+    pub webhook_dispatcher: WebhookDispatcher,
+    pub token_hasher: TokenHasher,
+    pub registration_mode: RegistrationMode,
 }
 
 impl AppState {
-    pub fn new(db_pool: sqlx::PgPool) -> Self {
+    pub fn new(db_pool: sqlx::PgPool, api_key_secret: &str, registration_mode: RegistrationMode) -> Self {
+        let repository = Repository::new(db_pool);
         Self {
-            repository: Repository::new(db_pool),
+            record_feed: RecordFeed::start(repository.clone()),
+            alert_evaluator: AlertEvaluator::start(repository.clone()),
+            webhook_dispatcher: WebhookDispatcher::start(repository.clone()),
+            token_hasher: TokenHasher::new(api_key_secret),
+            registration_mode,
+            repository,
         }
     }
 }
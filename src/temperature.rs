@@ -0,0 +1,218 @@
+use crate::calibration::{CalibratedValues, RecordCalibrations};
+use rerec::Reading;
+use rerec::record::Record;
+use serde::{Deserialize, Serialize};
+
+/// DS18B20 sensors report their temperature in millidegrees Celsius.
+const RAW_READINGS_PER_DEGREE_CELSIUS: f64 = 1000.0;
+const ABSOLUTE_ZERO_IN_CELSIUS: f64 = -273.15;
+/// Decoded temperatures are rounded to the number of decimals that the resolution of the raw
+/// reading justifies in any of the units, which also hides floating point noise.
+const DECIMALS: i32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum TemperatureUnit {
+    Celsius,
+    Fahrenheit,
+    Kelvin,
+}
+
+impl TryFrom<&str> for TemperatureUnit {
+    type Error = UnitError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.trim() {
+            "celsius" => Ok(TemperatureUnit::Celsius),
+            "fahrenheit" => Ok(TemperatureUnit::Fahrenheit),
+            "kelvin" => Ok(TemperatureUnit::Kelvin),
+            _ => Err(UnitError),
+        }
+    }
+}
+
+/// A comma separated list of temperature units, e.g. `celsius,kelvin`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String")]
+pub(crate) struct Units(Vec<TemperatureUnit>);
+
+impl Units {
+    pub fn contains(&self, unit: TemperatureUnit) -> bool {
+        self.0.contains(&unit)
+    }
+}
+
+impl Default for Units {
+    fn default() -> Self {
+        Units(vec![TemperatureUnit::Celsius])
+    }
+}
+
+impl TryFrom<String> for Units {
+    type Error = UnitError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let units = value
+            .split(',')
+            .map(TemperatureUnit::try_from)
+            .collect::<Result<Vec<TemperatureUnit>, UnitError>>()?;
+        Ok(Units(units))
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub(crate) struct UnitsQuery {
+    #[serde(default)]
+    pub units: Units,
+}
+
+/// A raw DS18B20 reading converted to degrees Celsius.
+pub(crate) fn celsius(raw_reading: i32) -> f64 {
+    round(unrounded_celsius(raw_reading))
+}
+
+/// A raw DS18B20 reading converted to degrees Fahrenheit.
+pub(crate) fn fahrenheit(raw_reading: i32) -> f64 {
+    round(celsius_to_fahrenheit(unrounded_celsius(raw_reading)))
+}
+
+/// A raw DS18B20 reading converted to kelvin.
+pub(crate) fn kelvin(raw_reading: i32) -> f64 {
+    round(celsius_to_kelvin(unrounded_celsius(raw_reading)))
+}
+
+/// A raw DS18B20 reading converted to degrees Celsius, at full precision for further calculations.
+pub(crate) fn unrounded_celsius(raw_reading: i32) -> f64 {
+    raw_reading as f64 / RAW_READINGS_PER_DEGREE_CELSIUS
+}
+
+fn celsius_to_fahrenheit(celsius: f64) -> f64 {
+    celsius * 9.0 / 5.0 + 32.0
+}
+
+fn celsius_to_kelvin(celsius: f64) -> f64 {
+    celsius - ABSOLUTE_ZERO_IN_CELSIUS
+}
+
+pub(crate) fn round(value: f64) -> f64 {
+    let factor = 10f64.powi(DECIMALS);
+    (value * factor).round() / factor
+}
+
+/// The temperature of a DS18B20 reading in the requested units.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct DecodedTemperature {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature_celsius: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature_fahrenheit: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature_kelvin: Option<f64>,
+}
+
+impl DecodedTemperature {
+    pub fn new(raw_reading: i32, units: &Units) -> Self {
+        Self::from_celsius(unrounded_celsius(raw_reading), units)
+    }
+
+    pub fn from_celsius(celsius: f64, units: &Units) -> Self {
+        Self {
+            temperature_celsius: units.contains(TemperatureUnit::Celsius).then(|| round(celsius)),
+            temperature_fahrenheit: units.contains(TemperatureUnit::Fahrenheit).then(|| round(celsius_to_fahrenheit(celsius))),
+            temperature_kelvin: units.contains(TemperatureUnit::Kelvin).then(|| round(celsius_to_kelvin(celsius))),
+        }
+    }
+}
+
+/// A record as returned by the API: the record itself, and for DS18B20 records, the decoded
+/// temperature next to it. Calibrated values, if any, are added separately. The raw reading is
+/// left untouched.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct DecodedRecord {
+    #[serde(flatten)]
+    record: Record,
+    #[serde(flatten)]
+    temperature: Option<DecodedTemperature>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    calibrated: Option<CalibratedValues>,
+    #[serde(skip)]
+    units: Units,
+}
+
+impl DecodedRecord {
+    pub fn new(record: Record, units: &Units) -> Self {
+        let temperature = match record.reading() {
+            Reading::DS18B20(reading) => Some(DecodedTemperature::new(reading.raw_reading(), units)),
+            Reading::BME280(_) => None,
+        };
+        Self { record, temperature, calibrated: None, units: units.clone() }
+    }
+
+    /// Adds the calibrated values of the record, if any of the calibrations apply to it.
+    pub fn calibrate(mut self, calibrations: &RecordCalibrations) -> Self {
+        self.calibrated = CalibratedValues::new(&self.record, calibrations, &self.units);
+        self
+    }
+}
+
+#[derive(Debug)]
+pub struct UnitError;
+
+impl std::fmt::Display for UnitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "units must be a comma separated list of celsius, fahrenheit and kelvin")
+    }
+}
+
+impl std::error::Error for UnitError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rerec::bme280::BME280;
+    use rerec::ds18b20::DS18B20;
+    use serde_json::json;
+    use sqlx::types::chrono::DateTime;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(celsius(22123), 22.123);
+        assert_eq!(celsius(-5500), -5.5);
+        assert_eq!(fahrenheit(0), 32.0);
+        assert_eq!(fahrenheit(100000), 212.0);
+        assert_eq!(kelvin(0), 273.15);
+        assert_eq!(kelvin(-273150), 0.0);
+        assert_eq!(fahrenheit(22123), 71.8214);
+        assert_eq!(kelvin(22123), 295.273);
+    }
+
+    #[test]
+    fn test_units_from_string() {
+        let units = Units::try_from("celsius,kelvin".to_string()).unwrap();
+        assert!(units.contains(TemperatureUnit::Celsius));
+        assert!(units.contains(TemperatureUnit::Kelvin));
+        assert!(!units.contains(TemperatureUnit::Fahrenheit));
+
+        assert!(Units::try_from("rankine".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_decoded_record_serialization() {
+        let id = Uuid::parse_str("7e9b1a33-05fb-48e3-86b6-21ddc873c06f").unwrap();
+        let timestamp = DateTime::from_timestamp(1772184765, 0).unwrap();
+
+        let reading = Reading::DS18B20(DS18B20::new("0000003e33d5".to_string(), 22123));
+        let record = DecodedRecord::new(Record::new(id, timestamp, reading), &Units::default());
+        assert_eq!(serde_json::to_value(record).unwrap(), json!({
+            "id": "7e9b1a33-05fb-48e3-86b6-21ddc873c06f",
+            "timestamp": "2026-02-27T09:32:45Z",
+            "reading": {"DS18B20": {"device_name": "0000003e33d5", "raw_reading": 22123}},
+            "temperature_celsius": 22.123,
+        }));
+
+        let reading = Reading::BME280(BME280::new(21.5, 100000.0, 45.0));
+        let record = DecodedRecord::new(Record::new(id, timestamp, reading), &Units::default());
+        assert!(serde_json::to_value(record).unwrap().get("temperature_celsius").is_none());
+    }
+}
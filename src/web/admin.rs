@@ -0,0 +1,203 @@
+use crate::authentication::api_key::ApiKeyListing;
+use crate::authentication::invite::Invite;
+use crate::authentication::token::Token;
+use crate::authentication::user::Role;
+use crate::authentication::user_auth::AdminUser;
+use crate::error::{AppError, AppResult};
+use crate::state::AppState;
+use crate::web::TERA;
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{Html, IntoResponse, Redirect};
+use axum::routing::{get, post};
+use axum::{Form, Router};
+use serde::Deserialize;
+use sqlx::types::chrono::Utc;
+use uuid::Uuid;
+
+pub(super) fn router() -> Router<AppState> {
+    Router::new()
+        .route("/users", get(users))
+        .route("/users/{user_id}/role", post(set_role))
+        .route("/users/{user_id}/disable", post(disable_user))
+        .route("/users/{user_id}/enable", post(enable_user))
+        .route("/users/{user_id}/approve", post(approve_user))
+        .route("/users/{user_id}/reject", post(reject_user))
+        .route("/users/{user_id}/reset_password", post(create_password_reset))
+        .route("/api_keys", get(api_keys))
+        .route("/invites", get(invites).post(create_invite))
+        .route("/invites/{invite_id}/delete", post(delete_invite))
+}
+
+async fn users(AdminUser(user): AdminUser, State(state): State<AppState>) -> AppResult<impl IntoResponse> {
+    let mut context = tera::Context::new();
+    context.insert("username", user.username());
+    context.insert("user_id", &user.id());
+    context.insert("users", &state.repository.list_users().await?);
+
+    let output = TERA.render("admin_users.html", &context)?;
+    Ok(Html(output))
+}
+
+async fn set_role(
+    AdminUser(user): AdminUser,
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+    Form(form_data): Form<RoleFormData>,
+) -> AppResult<impl IntoResponse> {
+    if user_id == user.id() && form_data.role != Role::Admin {
+        return Err(AppError::BadRequest("you cannot take the admin role from yourself"));
+    }
+    if !state.repository.set_user_role(user_id, form_data.role).await? {
+        return Err(AppError::NotFound(NOT_FOUND_MESSAGE));
+    }
+    tracing::info!("user {user_id} given the role {:?} by {}", form_data.role, user.username());
+    Ok(Redirect::to("/admin/users"))
+}
+
+/// Disables a user, who is logged out and can no longer log in or use their API keys.
+async fn disable_user(
+    AdminUser(user): AdminUser,
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    if user_id == user.id() {
+        return Err(AppError::BadRequest("you cannot disable yourself"));
+    }
+    if !state.repository.set_user_disabled(user_id, true).await? {
+        return Err(AppError::NotFound(NOT_FOUND_MESSAGE));
+    }
+    tracing::info!("user {user_id} disabled by {}", user.username());
+    Ok(Redirect::to("/admin/users"))
+}
+
+async fn enable_user(
+    AdminUser(user): AdminUser,
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    if !state.repository.set_user_disabled(user_id, false).await? {
+        return Err(AppError::NotFound(NOT_FOUND_MESSAGE));
+    }
+    tracing::info!("user {user_id} enabled by {}", user.username());
+    Ok(Redirect::to("/admin/users"))
+}
+
+async fn approve_user(
+    AdminUser(user): AdminUser,
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    if !state.repository.approve_user(user_id).await? {
+        return Err(AppError::NotFound(PENDING_NOT_FOUND_MESSAGE));
+    }
+    tracing::info!("user {user_id} approved by {}", user.username());
+    Ok(Redirect::to("/admin/users"))
+}
+
+/// Rejects a user awaiting approval by deleting them, which frees their username.
+async fn reject_user(
+    AdminUser(user): AdminUser,
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    if !state.repository.reject_user(user_id).await? {
+        return Err(AppError::NotFound(PENDING_NOT_FOUND_MESSAGE));
+    }
+    tracing::info!("user {user_id} rejected by {}", user.username());
+    Ok(Redirect::to("/admin/users"))
+}
+
+/// Issues a password reset token for the user, shown once, as only its hash is stored.
+async fn create_password_reset(
+    AdminUser(user): AdminUser,
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    let token = Token::new();
+    let token_hash = state.token_hasher.hash(&token.to_string());
+    let expires_at = state
+        .repository
+        .create_password_reset(user_id, user.id(), &token_hash)
+        .await?
+        .ok_or(AppError::NotFound(NOT_FOUND_MESSAGE))?;
+    tracing::info!("password reset for user {user_id} issued by {}", user.username());
+
+    let mut context = tera::Context::new();
+    context.insert("username", user.username());
+    context.insert("token", &token.to_string());
+    context.insert("expires_at", &expires_at);
+    let output = TERA.render("admin_password_reset.html", &context)?;
+
+    let mut res = (StatusCode::CREATED, Html(output)).into_response();
+    res.headers_mut().insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    Ok(res)
+}
+
+async fn api_keys(AdminUser(user): AdminUser, State(state): State<AppState>) -> AppResult<impl IntoResponse> {
+    let now = Utc::now();
+    let api_keys: Vec<ApiKeyListing> = state
+        .repository
+        .list_all_api_keys()
+        .await?
+        .into_iter()
+        .map(|key| ApiKeyListing::new(key, now))
+        .collect();
+
+    let mut context = tera::Context::new();
+    context.insert("username", user.username());
+    context.insert("api_keys", &api_keys);
+
+    let output = TERA.render("admin_api_keys.html", &context)?;
+    Ok(Html(output))
+}
+
+async fn invites(AdminUser(user): AdminUser, State(state): State<AppState>) -> AppResult<impl IntoResponse> {
+    let mut context = tera::Context::new();
+    context.insert("username", user.username());
+    context.insert("invites", &state.repository.list_invites().await?);
+
+    let output = TERA.render("admin_invites.html", &context)?;
+    Ok(Html(output))
+}
+
+/// Creates an invite and shows its code, which is only stored as a hash.
+async fn create_invite(
+    AdminUser(user): AdminUser,
+    State(state): State<AppState>,
+    Form(form_data): Form<RoleFormData>,
+) -> AppResult<impl IntoResponse> {
+    let (invite, code) = Invite::new(form_data.role, user.username());
+    let code_hash = state.token_hasher.hash(&code.to_string());
+    state.repository.create_invite(&invite, user.id(), &code_hash).await?;
+    tracing::info!("invite {} for a {:?} created by {}", invite.id, invite.role, user.username());
+
+    let mut context = tera::Context::new();
+    context.insert("username", user.username());
+    context.insert("invites", &state.repository.list_invites().await?);
+    context.insert("code", &code.to_string());
+    let output = TERA.render("admin_invites.html", &context)?;
+
+    let mut res = (StatusCode::CREATED, Html(output)).into_response();
+    res.headers_mut().insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    Ok(res)
+}
+
+async fn delete_invite(
+    AdminUser(_user): AdminUser,
+    State(state): State<AppState>,
+    Path(invite_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    if !state.repository.delete_invite(invite_id).await? {
+        return Err(AppError::NotFound("no invite with this id exists"));
+    }
+    Ok(Redirect::to("/admin/invites"))
+}
+
+const NOT_FOUND_MESSAGE: &str = "no user with this id exists";
+const PENDING_NOT_FOUND_MESSAGE: &str = "no user with this id is awaiting approval";
+
+#[derive(Debug, Clone, Deserialize)]
+struct RoleFormData {
+    role: Role,
+}
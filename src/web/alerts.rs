@@ -0,0 +1,87 @@
+use crate::alert::{AlertRule, AlertRuleDetails, Comparison};
+use crate::authentication::user_auth::{AuthUser, MemberUser};
+use crate::calibration::Quantity;
+use crate::error::{AppError, AppResult};
+use crate::state::AppState;
+use crate::web::TERA;
+use axum::extract::{Path, State};
+use axum::response::{Html, IntoResponse, Redirect};
+use axum::routing::{get, post};
+use axum::{Form, Router};
+use serde::Deserialize;
+use uuid::Uuid;
+
+/// How many alert events the history shows.
+const HISTORY_LIMIT: u32 = 200;
+
+pub(super) fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(alerts).post(create_alert_rule))
+        .route("/{rule_id}/delete", post(delete_alert_rule))
+}
+
+async fn alerts(user: AuthUser, State(state): State<AppState>) -> AppResult<impl IntoResponse> {
+    let mut context = tera::Context::new();
+    context.insert("username", user.username());
+    context.insert("rules", &state.repository.list_alert_rules(user.id()).await?);
+    context.insert("devices", &state.repository.list_devices(user.id()).await?);
+    context.insert("events", &state.repository.list_alert_history(user.id(), HISTORY_LIMIT).await?);
+
+    let output = TERA.render("alerts.html", &context)?;
+    Ok(Html(output))
+}
+
+async fn create_alert_rule(
+    MemberUser(user): MemberUser,
+    State(state): State<AppState>,
+    Form(form_data): Form<AlertRuleFormData>,
+) -> AppResult<impl IntoResponse> {
+    let details = AlertRuleDetails::from(form_data);
+    let device = state
+        .repository
+        .get_owned_device(user.id(), details.device_id)
+        .await?
+        .ok_or(AppError::BadRequest("no such device"))?;
+    details.validate(device.details.sensor).map_err(AppError::BadRequest)?;
+
+    state.repository.create_alert_rule(user.id(), &AlertRule::new(details)).await?;
+
+    Ok(Redirect::to("/alerts"))
+}
+
+async fn delete_alert_rule(
+    MemberUser(user): MemberUser,
+    State(state): State<AppState>,
+    Path(rule_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    state.repository.delete_alert_rule(user.id(), rule_id).await?;
+    Ok(Redirect::to("/alerts"))
+}
+
+/// The alert rule form, where the duration is entered in minutes and an empty webhook URL
+/// means no webhook.
+#[derive(Debug, Clone, Deserialize)]
+struct AlertRuleFormData {
+    name: String,
+    device_id: Uuid,
+    quantity: Quantity,
+    comparison: Comparison,
+    threshold: f64,
+    duration_minutes: u16,
+    webhook_url: String,
+}
+
+impl From<AlertRuleFormData> for AlertRuleDetails {
+    fn from(form_data: AlertRuleFormData) -> Self {
+        let webhook_url = Some(form_data.webhook_url.trim().to_string()).filter(|url| !url.is_empty());
+        AlertRuleDetails {
+            name: form_data.name.trim().to_string(),
+            device_id: form_data.device_id,
+            quantity: form_data.quantity,
+            comparison: form_data.comparison,
+            threshold: form_data.threshold,
+            duration_seconds: i32::from(form_data.duration_minutes) * 60,
+            webhook_url,
+        }
+    }
+}
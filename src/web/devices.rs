@@ -0,0 +1,218 @@
+use crate::authentication::user_auth::{AuthUser, MemberUser};
+use crate::calibration::{Calibration, CalibrationDetails, Quantity};
+use crate::device::{Device, DeviceDetails, HealthQuery, SensorType};
+use crate::error::{AppError, AppResult};
+use crate::state::AppState;
+use crate::web::{parse_utc, TERA};
+use axum::extract::{Path, State};
+use axum::response::{Html, IntoResponse, Redirect};
+use axum::routing::{get, post};
+use axum::{Form, Router};
+use axum_extra::extract::Query;
+use serde::Deserialize;
+use sqlx::types::chrono::Utc;
+use uuid::Uuid;
+
+pub(super) fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(devices).post(create_device))
+        .route("/health", get(health))
+        .route("/{device_id}", get(device).post(update_device))
+        .route("/{device_id}/delete", post(delete_device))
+        .route("/{device_id}/calibrations", post(create_calibration))
+        .route("/{device_id}/calibrations/{calibration_id}/delete", post(delete_calibration))
+}
+
+async fn devices(user: AuthUser, State(state): State<AppState>) -> AppResult<impl IntoResponse> {
+    let mut context = tera::Context::new();
+    context.insert("username", user.username());
+    context.insert("devices", &state.repository.list_devices(user.id()).await?);
+    context.insert("api_keys", &state.repository.list_api_keys(user.id()).await?);
+
+    let output = TERA.render("devices.html", &context)?;
+    Ok(Html(output))
+}
+
+/// Lists the devices which have been silent for longer than the expected interval.
+async fn health(
+    MemberUser(user): MemberUser,
+    State(state): State<AppState>,
+    Query(HealthQuery { expected_interval }): Query<HealthQuery>,
+) -> AppResult<impl IntoResponse> {
+    let now = Utc::now();
+    let silent: Vec<_> = state
+        .repository
+        .list_device_health(user.id())
+        .await?
+        .into_iter()
+        .map(|health| health.check(now, expected_interval))
+        .filter(|health| health.silent)
+        .collect();
+
+    let mut context = tera::Context::new();
+    context.insert("username", user.username());
+    context.insert("expected_interval", &expected_interval.to_string());
+    context.insert("devices", &silent);
+
+    let output = TERA.render("device_health.html", &context)?;
+    Ok(Html(output))
+}
+
+async fn device(
+    MemberUser(user): MemberUser,
+    State(state): State<AppState>,
+    Path(device_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    let device = state
+        .repository
+        .get_device_by_id(device_id, user.id())
+        .await?
+        .ok_or(AppError::NotFound(NOT_FOUND_MESSAGE))?;
+
+    let mut context = tera::Context::new();
+    context.insert("username", user.username());
+    context.insert("device", &device);
+    context.insert("api_keys", &state.repository.list_api_keys(user.id()).await?);
+    context.insert("calibrations", &state.repository.list_calibrations(device_id).await?);
+
+    let output = TERA.render("device.html", &context)?;
+    Ok(Html(output))
+}
+
+async fn create_device(
+    MemberUser(user): MemberUser,
+    State(state): State<AppState>,
+    Form(form_data): Form<DeviceFormData>,
+) -> AppResult<impl IntoResponse> {
+    let device = Device::new(DeviceDetails::try_from(form_data)?);
+    state
+        .repository
+        .create_device(user.id(), &device)
+        .await
+        .map_err(AppError::from_commit_device_error)?;
+
+    Ok(Redirect::to("/devices"))
+}
+
+async fn update_device(
+    MemberUser(user): MemberUser,
+    State(state): State<AppState>,
+    Path(device_id): Path<Uuid>,
+    Form(form_data): Form<DeviceFormData>,
+) -> AppResult<impl IntoResponse> {
+    let device = Device { id: device_id, details: DeviceDetails::try_from(form_data)? };
+    let updated = state
+        .repository
+        .update_device(user.id(), &device)
+        .await
+        .map_err(AppError::from_commit_device_error)?;
+
+    if !updated {
+        return Err(AppError::NotFound(NOT_FOUND_MESSAGE));
+    }
+
+    Ok(Redirect::to("/devices"))
+}
+
+async fn delete_device(
+    MemberUser(user): MemberUser,
+    State(state): State<AppState>,
+    Path(device_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    state.repository.delete_device(user.id(), device_id).await?;
+    Ok(Redirect::to("/devices"))
+}
+
+async fn create_calibration(
+    MemberUser(user): MemberUser,
+    State(state): State<AppState>,
+    Path(device_id): Path<Uuid>,
+    Form(form_data): Form<CalibrationFormData>,
+) -> AppResult<impl IntoResponse> {
+    let device = state
+        .repository
+        .get_owned_device(user.id(), device_id)
+        .await?
+        .ok_or(AppError::NotFound(NOT_FOUND_MESSAGE))?;
+
+    let details = CalibrationDetails::try_from(form_data)?;
+    details.validate(device.details.sensor).map_err(AppError::BadRequest)?;
+    state.repository.create_calibration(&Calibration::new(device_id, details)).await?;
+
+    Ok(Redirect::to(&format!("/devices/{device_id}")))
+}
+
+async fn delete_calibration(
+    MemberUser(user): MemberUser,
+    State(state): State<AppState>,
+    Path((device_id, calibration_id)): Path<(Uuid, Uuid)>,
+) -> AppResult<impl IntoResponse> {
+    state.repository.delete_calibration(user.id(), device_id, calibration_id).await?;
+    Ok(Redirect::to(&format!("/devices/{device_id}")))
+}
+
+const NOT_FOUND_MESSAGE: &str = "No device with the supplied id was found";
+
+/// The device form, where fields left empty are submitted as empty strings.
+#[derive(Debug, Clone, Deserialize)]
+struct DeviceFormData {
+    sensor: SensorType,
+    serial: String,
+    api_key_id: String,
+    name: String,
+    location: String,
+    description: String,
+}
+
+impl TryFrom<DeviceFormData> for DeviceDetails {
+    type Error = AppError;
+
+    fn try_from(form_data: DeviceFormData) -> Result<Self, Self::Error> {
+        let serial = Some(form_data.serial.trim().to_string()).filter(|serial| !serial.is_empty());
+        let api_key_id = match form_data.api_key_id.trim() {
+            "" => None,
+            api_key_id => Some(Uuid::parse_str(api_key_id).map_err(|_| AppError::BadRequest("invalid API key id"))?),
+        };
+
+        let details = DeviceDetails {
+            sensor: form_data.sensor,
+            serial,
+            api_key_id,
+            name: form_data.name.trim().to_string(),
+            location: form_data.location.trim().to_string(),
+            description: form_data.description.trim().to_string(),
+        };
+        details.validate().map_err(AppError::BadRequest)?;
+
+        Ok(details)
+    }
+}
+
+/// The calibration form. Times are entered without a time zone and taken as UTC.
+#[derive(Debug, Clone, Deserialize)]
+struct CalibrationFormData {
+    quantity: Quantity,
+    gain: f64,
+    offset: f64,
+    valid_from: String,
+    valid_to: String,
+}
+
+impl TryFrom<CalibrationFormData> for CalibrationDetails {
+    type Error = AppError;
+
+    fn try_from(form_data: CalibrationFormData) -> Result<Self, Self::Error> {
+        let valid_to = match form_data.valid_to.trim() {
+            "" => None,
+            valid_to => Some(parse_utc(valid_to)?),
+        };
+
+        Ok(CalibrationDetails {
+            quantity: form_data.quantity,
+            gain: form_data.gain,
+            offset: form_data.offset,
+            valid_from: parse_utc(&form_data.valid_from)?,
+            valid_to,
+        })
+    }
+}
@@ -1,15 +1,28 @@
 use crate::state::AppState;
-use axum::extract::State;
+use std::collections::HashMap;
+use axum::extract::{Query, State};
 use axum::http::StatusCode;
-use axum::response::{Html, IntoResponse};
+use axum::response::{Html, IntoResponse, Response};
 use axum::routing::get;
 use axum::Router;
 use record_view::RecordView;
+use crate::authentication::api_key::ApiKeyListing;
 use crate::authentication::user_api;
 use crate::authentication::user_auth::AuthUser;
+use crate::temperature::UnitsQuery;
+use crate::api::RecordFilter;
+use crate::device::SensorType;
+use crate::error::{AppError, AppResult};
+use crate::export::{csv_response, Columns};
 use crate::web::record_view::{Bme280RecordView, Ds18b20RecordView};
+use sqlx::types::chrono::{DateTime, NaiveDateTime, Utc};
 
+mod admin;
+mod alerts;
+mod devices;
 mod record_view;
+mod shares;
+mod webhooks;
 
 pub static TERA: std::sync::LazyLock<tera::Tera> = std::sync::LazyLock::new(|| {
     match tera::Tera::new("templates/**/*") {
@@ -26,11 +39,21 @@ pub(crate) fn web() -> Router<AppState> {
         .route("/", get(index))
         .route("/me", get(me))
         .route("/login", get(login))
+        .route("/login/totp", get(login_totp))
         .route("/register", get(register))
+        .route("/reset_password", get(reset_password))
         .route("/api_keys", get(api_keys))
         .route("/records", get(records))
+        .route("/records.csv", get(records_csv))
         .route("/bme280", get(bme280))
+        .route("/bme280.csv", get(bme280_csv))
         .route("/ds18b20", get(ds18b20))
+        .route("/ds18b20.csv", get(ds18b20_csv))
+        .nest("/devices", devices::router())
+        .nest("/alerts", alerts::router())
+        .nest("/webhooks", webhooks::router())
+        .nest("/shares", shares::router())
+        .nest("/admin", admin::router())
         .nest("/users",user_api::user_router())
 }
 
@@ -44,20 +67,26 @@ async fn index(user: Result<AuthUser, impl IntoResponse>) -> impl IntoResponse {
     Html(output).into_response()
 }
 
-async fn me(user: AuthUser) -> impl IntoResponse {
+async fn me(user: AuthUser, State(state): State<AppState>) -> AppResult<impl IntoResponse> {
     let mut context = tera::Context::new();
     context.insert("username", user.username());
     context.insert("user_id", &user.id());
+    context.insert("role", &user.role());
+    let totp_enabled = state.repository.get_totp_secret(user.id()).await?.is_some();
+    context.insert("totp_enabled", &totp_enabled);
+    if totp_enabled {
+        context.insert("recovery_codes", &state.repository.count_recovery_codes(user.id()).await?);
+    }
 
     let template = "me.html";
 
     match TERA.render(template, &context) {
         Ok(output) => {
-            Html(output).into_response()
+            Ok(Html(output).into_response())
         },
         Err(error) => {
             tracing::warn!("Error rendering template ({}): {}", template, error);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response())
         }
     }
 }
@@ -68,17 +97,39 @@ async fn login() -> impl IntoResponse {
     Html(output)
 }
 
-async fn register() -> impl IntoResponse {
+async fn login_totp() -> impl IntoResponse {
     let context = tera::Context::new();
+    let output = TERA.render("login_totp.html", &context).unwrap();
+    Html(output)
+}
+
+async fn register(State(state): State<AppState>) -> impl IntoResponse {
+    let mut context = tera::Context::new();
+    context.insert("mode", &state.registration_mode);
     let output = TERA.render("register.html", &context).unwrap();
     Html(output)
 }
 
+async fn reset_password(Query(query): Query<HashMap<String, String>>) -> AppResult<impl IntoResponse> {
+    let mut context = tera::Context::new();
+    context.insert("token", query.get("token").map(String::as_str).unwrap_or_default());
+    let output = TERA.render("reset_password.html", &context)?;
+    Ok(Html(output))
+}
+
 async fn api_keys(user: AuthUser, State(state): State<AppState>) -> impl IntoResponse {
     let mut context = tera::Context::new();
     context.insert("username", user.username());
 
-    let api_keys = state.repository.list_api_keys().await.unwrap();
+    let now = Utc::now();
+    let api_keys: Vec<ApiKeyListing> = state
+        .repository
+        .list_api_keys(user.id())
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|key| ApiKeyListing::new(key, now))
+        .collect();
     context.insert("api_keys", &api_keys);
 
     let output = TERA.render("api_keys.html", &context).unwrap();
@@ -89,7 +140,7 @@ async fn records(user: AuthUser, State(state): State<AppState>) -> impl IntoResp
     let mut context = tera::Context::new();
     context.insert("username", user.username());
 
-    let records = state.repository.get_records().await.unwrap();
+    let records = state.repository.get_records(user.id()).await.unwrap();
     let records: Vec<RecordView> = records.into_iter().map(|r| r.into()).collect();
     context.insert("records", &records);
     let output = TERA.render("records.html", &context).unwrap();
@@ -100,7 +151,7 @@ async fn bme280(user: AuthUser, State(state): State<AppState>) -> impl IntoRespo
     let mut context = tera::Context::new();
     context.insert("username", user.username());
 
-    let records = state.repository.get_all_bme280_records().await.unwrap();
+    let records = state.repository.get_all_bme280_records(user.id()).await.unwrap();
     let records: Vec<Bme280RecordView> = records
         .into_iter()
         .map(|r| r
@@ -112,25 +163,69 @@ async fn bme280(user: AuthUser, State(state): State<AppState>) -> impl IntoRespo
     Html(output).into_response()
 }
 
-async fn ds18b20(user: AuthUser, State(state): State<AppState>) -> impl IntoResponse {
+async fn ds18b20(
+    user: AuthUser,
+    State(state): State<AppState>,
+    Query(UnitsQuery { units }): Query<UnitsQuery>,
+) -> impl IntoResponse {
     let mut context = tera::Context::new();
     context.insert("username", user.username());
+    context.insert("units", &units);
 
-    let records = state.repository.get_all_ds18b20_records().await.unwrap();
+    let records = state.repository.get_all_named_ds18b20_records(user.id()).await.unwrap();
     let records: Vec<Ds18b20RecordView> = records
         .into_iter()
-        .map(|r| r
-            .try_into()
-            .expect("the repository only delivers convertible records"))
+        .map(|(r, device)| Ds18b20RecordView::try_from(r)
+            .expect("the repository only delivers convertible records")
+            .with_device(device))
         .collect();
     context.insert("records", &records);
     let output = TERA.render("ds18b20.html", &context).unwrap();
     Html(output).into_response()
 }
 
+async fn records_csv(
+    user: AuthUser,
+    State(state): State<AppState>,
+    axum_extra::extract::Query(filter): axum_extra::extract::Query<RecordFilter>,
+) -> AppResult<Response> {
+    filter.check_since_id(&state.repository, None, user.id()).await?;
+    let records = state.repository.stream_records_by_filter(filter.with_default_limit(), user.id());
+    Ok(csv_response(records, Columns::All, "records.csv"))
+}
+
+async fn bme280_csv(
+    user: AuthUser,
+    State(state): State<AppState>,
+    axum_extra::extract::Query(filter): axum_extra::extract::Query<RecordFilter>,
+) -> AppResult<Response> {
+    filter.check_since_id(&state.repository, Some(SensorType::Bme280), user.id()).await?;
+    let records = state.repository.stream_bme280_by_filter(filter.with_default_limit(), user.id());
+    Ok(csv_response(records, Columns::Bme280, "bme280.csv"))
+}
+
+async fn ds18b20_csv(
+    user: AuthUser,
+    State(state): State<AppState>,
+    axum_extra::extract::Query(filter): axum_extra::extract::Query<RecordFilter>,
+) -> AppResult<Response> {
+    filter.check_since_id(&state.repository, Some(SensorType::Ds18b20), user.id()).await?;
+    let records = state.repository.stream_ds18b20_by_filter(filter.with_default_limit(), user.id());
+    Ok(csv_response(records, Columns::Ds18b20, "ds18b20.csv"))
+}
+
+/// Parses the value of a `datetime-local` input, with or without seconds.
+pub(crate) fn parse_utc(value: &str) -> Result<DateTime<Utc>, AppError> {
+    NaiveDateTime::parse_from_str(value.trim(), "%Y-%m-%dT%H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(value.trim(), "%Y-%m-%dT%H:%M:%S"))
+        .map(|timestamp| timestamp.and_utc())
+        .map_err(|_| AppError::BadRequest("invalid date and time"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::authentication::registration::RegistrationMode;
 
     #[tokio::test]
     async fn test_render_index() {
@@ -149,12 +244,28 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_register() {
-        let response = register().await.into_response();
+    async fn test_reset_password() {
+        let query = HashMap::from([("token".to_string(), "reset-token".to_string())]);
+        let response = reset_password(Query(query)).await.into_response();
         assert_eq!(response.status(), StatusCode::OK);
 
         let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
         let body_str = String::from_utf8(body.to_vec()).unwrap();
-        assert!(body_str.contains("<h1>Create a new user account</h1>"));
+        assert!(body_str.contains(r#"value="reset-token""#));
+    }
+
+    #[tokio::test]
+    async fn test_render_register() {
+        for (mode, text) in [
+            (RegistrationMode::Open, "Anyone may register."),
+            (RegistrationMode::Invite, "Registering requires an invite code"),
+            (RegistrationMode::Approval, "an admin must approve the account"),
+        ] {
+            let mut context = tera::Context::new();
+            context.insert("mode", &mode);
+            let page = TERA.render("register.html", &context).unwrap();
+            assert!(page.contains("<h1>Create a new user account</h1>"));
+            assert!(page.contains(text));
+        }
     }
 }
@@ -3,6 +3,7 @@ use serde::{Deserialize, Serialize};
 use rerec::record::Record;
 use sqlx::types::chrono;
 use uuid::Uuid;
+use crate::temperature;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RecordView {
@@ -34,10 +35,23 @@ pub(super) struct Bme280RecordView {
 pub(super) struct Ds18b20RecordView {
     id: Uuid,
     device_name: String,
+    /// The friendly name of the registered device, if the device is registered.
+    device: Option<String>,
     raw_reading: i32,
+    temperature_celsius: f64,
+    temperature_fahrenheit: f64,
+    temperature_kelvin: f64,
     timestamp: chrono::DateTime<chrono::Utc>,
 }
 
+impl Ds18b20RecordView {
+    /// Names the device of the record, given the friendly name of its registered device.
+    pub fn with_device(mut self, device: Option<String>) -> Self {
+        self.device = device;
+        self
+    }
+}
+
 impl TryFrom<Record> for Bme280RecordView {
     type Error = ();
 
@@ -67,7 +81,11 @@ impl TryFrom<Record> for Ds18b20RecordView {
                 Ok(Ds18b20RecordView {
                     id: value.id(),
                     device_name: reading.device_name().to_string(),
+                    device: None,
                     raw_reading: reading.raw_reading(),
+                    temperature_celsius: temperature::celsius(reading.raw_reading()),
+                    temperature_fahrenheit: temperature::fahrenheit(reading.raw_reading()),
+                    temperature_kelvin: temperature::kelvin(reading.raw_reading()),
                     timestamp: value.timestamp(),
                 })
             }
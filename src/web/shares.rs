@@ -0,0 +1,64 @@
+use crate::authentication::user_auth::{AuthUser, MemberUser};
+use crate::error::{AppError, AppResult};
+use crate::state::AppState;
+use crate::web::TERA;
+use axum::extract::{Path, State};
+use axum::response::{Html, IntoResponse, Redirect};
+use axum::routing::{get, post};
+use axum::{Form, Router};
+use serde::Deserialize;
+use uuid::Uuid;
+
+pub(super) fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(shares).post(create_share))
+        .route("/{grantee_id}/delete", post(delete_share))
+}
+
+async fn shares(user: AuthUser, State(state): State<AppState>) -> AppResult<impl IntoResponse> {
+    let (granted, received) = state
+        .repository
+        .list_shares(user.id())
+        .await?
+        .into_iter()
+        .partition::<Vec<_>, _>(|share| share.owner_id == user.id());
+
+    let mut context = tera::Context::new();
+    context.insert("username", user.username());
+    context.insert("granted", &granted);
+    context.insert("received", &received);
+
+    let output = TERA.render("shares.html", &context)?;
+    Ok(Html(output))
+}
+
+async fn create_share(
+    MemberUser(user): MemberUser,
+    State(state): State<AppState>,
+    Form(form_data): Form<ShareFormData>,
+) -> AppResult<impl IntoResponse> {
+    let grantee = form_data.grantee.trim();
+    if grantee == user.username() {
+        return Err(AppError::BadRequest("your records are always readable by yourself"));
+    }
+
+    if !state.repository.create_share(user.id(), grantee).await? {
+        return Err(AppError::BadRequest("no user with this username exists"));
+    }
+
+    Ok(Redirect::to("/shares"))
+}
+
+async fn delete_share(
+    MemberUser(user): MemberUser,
+    State(state): State<AppState>,
+    Path(grantee_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    state.repository.delete_share(user.id(), grantee_id).await?;
+    Ok(Redirect::to("/shares"))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ShareFormData {
+    grantee: String,
+}
@@ -0,0 +1,102 @@
+use crate::authentication::user_auth::{AuthUser, MemberUser};
+use crate::device::SensorType;
+use crate::error::{AppError, AppResult};
+use crate::feed::FeedFilter;
+use crate::state::AppState;
+use crate::web::TERA;
+use crate::webhook::{Webhook, DELIVERY_HEADER, SIGNATURE_HEADER};
+use axum::extract::{Path, State};
+use axum::response::{Html, IntoResponse, Redirect};
+use axum::routing::{get, post};
+use axum::{Form, Router};
+use serde::Deserialize;
+use uuid::Uuid;
+
+/// How many delivery attempts the log shows.
+const DELIVERY_LOG_LIMIT: u32 = 200;
+
+pub(super) fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(webhooks).post(create_webhook))
+        .route("/{webhook_id}/delete", post(delete_webhook))
+}
+
+async fn webhooks(user: AuthUser, State(state): State<AppState>) -> AppResult<impl IntoResponse> {
+    let mut context = tera::Context::new();
+    context.insert("username", user.username());
+    context.insert("webhooks", &state.repository.list_webhooks(user.id()).await?);
+    context.insert("deliveries", &state.repository.list_webhook_deliveries(user.id(), DELIVERY_LOG_LIMIT).await?);
+    context.insert("signature_header", SIGNATURE_HEADER);
+    context.insert("delivery_header", DELIVERY_HEADER);
+
+    let output = TERA.render("webhooks.html", &context)?;
+    Ok(Html(output))
+}
+
+async fn create_webhook(
+    MemberUser(user): MemberUser,
+    State(state): State<AppState>,
+    Form(form_data): Form<WebhookFormData>,
+) -> AppResult<impl IntoResponse> {
+    let webhook = Webhook::new(user.id(), form_data.url.trim().to_string(), form_data.filter());
+    webhook.validate().map_err(AppError::BadRequest)?;
+    state.repository.create_webhook(&webhook).await?;
+
+    Ok(Redirect::to("/webhooks"))
+}
+
+async fn delete_webhook(
+    MemberUser(user): MemberUser,
+    State(state): State<AppState>,
+    Path(webhook_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    state.repository.delete_webhook(user.id(), webhook_id).await?;
+    Ok(Redirect::to("/webhooks"))
+}
+
+/// The webhook form, where an empty sensor means any sensor type and device names are
+/// separated by commas.
+#[derive(Debug, Clone, Deserialize)]
+struct WebhookFormData {
+    url: String,
+    sensor: String,
+    device_names: String,
+}
+
+impl WebhookFormData {
+    fn filter(&self) -> FeedFilter {
+        let sensor = match self.sensor.as_str() {
+            "bme280" => vec![SensorType::Bme280],
+            "ds18b20" => vec![SensorType::Ds18b20],
+            _ => vec![],
+        };
+        let device_name = self
+            .device_names
+            .split(',')
+            .map(str::trim)
+            .filter(|device_name| !device_name.is_empty())
+            .map(str::to_string)
+            .collect();
+        FeedFilter { sensor, device_name }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_form_filter() {
+        let form_data = WebhookFormData {
+            url: "https://example.com/records".to_string(),
+            sensor: "ds18b20".to_string(),
+            device_names: " 0000003e33d5, ,000000a1b2c3".to_string(),
+        };
+        let filter = form_data.filter();
+        assert_eq!(filter.sensor, vec![SensorType::Ds18b20]);
+        assert_eq!(filter.device_name, vec!["0000003e33d5", "000000a1b2c3"]);
+
+        let form_data = WebhookFormData { sensor: String::new(), device_names: String::new(), ..form_data };
+        assert!(form_data.filter().sensor.is_empty());
+    }
+}
@@ -0,0 +1,352 @@
+use crate::authentication::token::{encode_hex, Token};
+use crate::feed::{CommittedRecord, FeedFilter};
+use crate::repository::Repository;
+use hmac::{Hmac, Mac};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use rerec::record::Record;
+use serde::Serialize;
+use sha2::Sha256;
+use sqlx::types::chrono::{DateTime, Utc};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+/// How long to wait for a webhook receiver to respond.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// How many committed records may wait for delivery before further records are not delivered.
+const CAPACITY: usize = 1024;
+/// How many times a record is sent to a webhook before giving up.
+const MAX_ATTEMPTS: i32 = 5;
+/// How long to wait before the first retry. The delay doubles for every further retry.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The header holding the signature of the body, as `sha256=` followed by the hexadecimal
+/// HMAC-SHA256 of the body keyed with the secret of the webhook.
+pub(crate) const SIGNATURE_HEADER: &str = "X-Herodot-Signature";
+/// The header identifying the delivery of a record, the same for every attempt.
+pub(crate) const DELIVERY_HEADER: &str = "X-Herodot-Delivery";
+
+/// A client for POSTing to webhooks.
+///
+/// Host names are only resolved to public addresses, so a webhook cannot reach the server
+/// itself, its network or the metadata service of a cloud provider. Redirects are not followed,
+/// as they could lead to such an address by IP.
+pub(crate) fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .dns_resolver(Arc::new(PublicResolver))
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("the webhook client configuration is valid")
+}
+
+/// Checks that the URL is an http or https URL that does not name a non-public address.
+///
+/// Host names are not resolved here but by the [`client`], which refuses non-public addresses
+/// when connecting, so a name cannot be pointed at another address after it was checked.
+pub(crate) fn check_url(url: &str) -> Result<(), &'static str> {
+    let Ok(url) = reqwest::Url::parse(url) else {
+        return Err("webhook URL must be an http or https URL");
+    };
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err("webhook URL must be an http or https URL");
+    }
+
+    // IPv6 addresses are enclosed in brackets.
+    let is_public = match url.host_str().map(|host| host.trim_start_matches('[').trim_end_matches(']')) {
+        Some(host) => match host.parse::<IpAddr>() {
+            Ok(address) => is_public(address),
+            Err(_) => !host.eq_ignore_ascii_case("localhost"),
+        },
+        None => false,
+    };
+    if !is_public {
+        return Err("webhook URL must not point to a loopback, private or link-local address");
+    }
+    Ok(())
+}
+
+/// Whether the address is reachable on the public internet, as opposed to loopback, private,
+/// link-local (including cloud metadata services), shared, multicast or reserved addresses.
+fn is_public(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => {
+            let [a, b, ..] = address.octets();
+            !(address.is_unspecified()
+                || address.is_loopback()
+                || address.is_private()
+                || address.is_link_local()
+                || address.is_broadcast()
+                || address.is_documentation()
+                || address.is_multicast()
+                || a == 0
+                // Shared address space of carrier-grade NAT, 100.64.0.0/10.
+                || (a == 100 && (64..128).contains(&b))
+                // Benchmarking, 198.18.0.0/15.
+                || (a == 198 && (18..20).contains(&b))
+                // IETF protocol assignments, 192.0.0.0/24, and reserved, 240.0.0.0/4.
+                || (a == 192 && b == 0 && address.octets()[2] == 0)
+                || a >= 240)
+        }
+        IpAddr::V6(address) => {
+            if let Some(address) = address.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(address));
+            }
+            let first = address.segments()[0];
+            !(address.is_unspecified()
+                || address.is_loopback()
+                || address.is_multicast()
+                // Unique local, fc00::/7.
+                || (first & 0xfe00) == 0xfc00
+                // Link-local, fe80::/10.
+                || (first & 0xffc0) == 0xfe80
+                // Documentation, 2001:db8::/32.
+                || (first == 0x2001 && address.segments()[1] == 0x0db8))
+        }
+    }
+}
+
+/// Resolves host names like the system does, but only to public addresses.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|address| is_public(address.ip()))
+                .collect();
+            if addresses.is_empty() {
+                return Err(format!("{host} does not resolve to a public address").into());
+            }
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// An endpoint registered by a user to receive every committed record matching its filter.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub(crate) struct Webhook {
+    pub id: Uuid,
+    pub owner_id: Uuid,
+    pub url: String,
+    /// The key the records sent to the webhook are signed with.
+    pub secret: String,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub filter: FeedFilter,
+}
+
+impl Webhook {
+    pub fn new(owner_id: Uuid, url: String, filter: FeedFilter) -> Self {
+        Self { id: Uuid::new_v4(), owner_id, url, secret: Token::new().to_string(), filter }
+    }
+
+    /// Checks the URL with [`check_url`].
+    pub fn validate(&self) -> Result<(), &'static str> {
+        check_url(&self.url)
+    }
+}
+
+/// One attempt at sending a record to a webhook.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub(crate) struct WebhookDelivery {
+    pub id: Uuid,
+    /// Identifies the delivery of the record to the webhook, across attempts.
+    pub delivery_id: Uuid,
+    pub webhook_id: Uuid,
+    pub record_id: Uuid,
+    pub attempt: i32,
+    /// The response status, or `None` if no response was received.
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub succeeded: bool,
+    pub attempted_at: DateTime<Utc>,
+}
+
+/// Delivers newly committed records to the webhooks registered for them, in the background.
+///
+/// A record is only delivered to the webhooks of users who may read it.
+#[derive(Clone)]
+pub(crate) struct WebhookDispatcher {
+    sender: mpsc::Sender<CommittedRecord>,
+}
+
+impl WebhookDispatcher {
+    pub fn start(repository: Repository) -> Self {
+        let (sender, receiver) = mpsc::channel(CAPACITY);
+        tokio::spawn(dispatch(repository, receiver));
+        Self { sender }
+    }
+
+    /// Queues committed records of the owner for delivery without waiting for them to be
+    /// delivered.
+    pub fn enqueue(&self, owner_id: Uuid, records: &[Record]) {
+        for record in records {
+            let committed = CommittedRecord { owner_id, record: record.clone() };
+            if let Err(error) = self.sender.try_send(committed) {
+                tracing::warn!("Not delivering record {} to webhooks: {error}", record.id());
+            }
+        }
+    }
+}
+
+async fn dispatch(repository: Repository, mut receiver: mpsc::Receiver<CommittedRecord>) {
+    let client = client();
+
+    while let Some(CommittedRecord { owner_id, record }) = receiver.recv().await {
+        let webhooks = match repository.list_webhooks_receiving(owner_id).await {
+            Ok(webhooks) => webhooks,
+            Err(error) => {
+                tracing::warn!("Unable to look up webhooks for record {}: {error}", record.id());
+                continue;
+            }
+        };
+
+        let body = match serde_json::to_vec(&record) {
+            Ok(body) => body,
+            Err(error) => {
+                tracing::warn!("Unable to serialize record {} for webhooks: {error}", record.id());
+                continue;
+            }
+        };
+
+        for webhook in webhooks.into_iter().filter(|webhook| webhook.filter.matches(&record)) {
+            tokio::spawn(deliver(client.clone(), repository.clone(), webhook, record.id(), body.clone()));
+        }
+    }
+}
+
+/// Sends the record to the webhook until it is accepted or the attempts run out, logging every
+/// attempt.
+async fn deliver(client: reqwest::Client, repository: Repository, webhook: Webhook, record_id: Uuid, body: Vec<u8>) {
+    let delivery_id = Uuid::new_v4();
+    let signature = sign(&webhook.secret, &body);
+    let mut backoff = INITIAL_BACKOFF;
+
+    // Checked again as the stored URL may predate a tightening of the check. Host names are
+    // checked as they resolve, but IP literals never reach the resolver.
+    let url_error = check_url(&webhook.url).err();
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        let response = match url_error {
+            Some(error) => Err(error.to_string()),
+            None => client
+                .post(&webhook.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(SIGNATURE_HEADER, &signature)
+                .header(DELIVERY_HEADER, delivery_id.to_string())
+                .body(body.clone())
+                .send()
+                .await
+                .map_err(|error| error_chain(&error)),
+        };
+
+        let (status_code, error) = match response {
+            Ok(response) => (Some(i32::from(response.status().as_u16())), None),
+            Err(error) => (None, Some(error)),
+        };
+        let succeeded = status_code.is_some_and(|status_code| (200..300).contains(&status_code));
+
+        let delivery = WebhookDelivery {
+            id: Uuid::new_v4(),
+            delivery_id,
+            webhook_id: webhook.id,
+            record_id,
+            attempt,
+            status_code,
+            error,
+            succeeded,
+            attempted_at: Utc::now(),
+        };
+        if let Err(error) = repository.create_webhook_delivery(&delivery).await {
+            tracing::warn!("Unable to log delivery of record {record_id} to webhook {}: {error}", webhook.id);
+        }
+
+        if succeeded || url_error.is_some() {
+            return;
+        }
+
+        if attempt < MAX_ATTEMPTS {
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+
+    tracing::warn!("Gave up delivering record {record_id} to webhook {}", webhook.id);
+}
+
+/// The error along with its causes, which tell why a request could not be sent, e.g. that the
+/// host does not resolve to a public address.
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+/// The value of the signature header for a body.
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    format!("sha256={}", encode_hex(&mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::SensorType;
+
+    #[test]
+    fn test_sign() {
+        // Test case 2 of RFC 4231.
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let filter = FeedFilter { sensor: vec![SensorType::Ds18b20], device_name: vec![] };
+        let webhook = Webhook::new(Uuid::new_v4(), "https://example.com/records".to_string(), filter);
+        assert!(webhook.validate().is_ok());
+        assert!(!webhook.secret.is_empty());
+
+        let webhook = Webhook { url: "example.com/records".to_string(), ..webhook };
+        assert!(webhook.validate().is_err());
+    }
+
+    #[test]
+    fn test_check_url() {
+        assert!(check_url("https://example.com/records").is_ok());
+        assert!(check_url("http://93.184.215.14:8080/records").is_ok());
+        assert!(check_url("http://[2606:2800:21f:cb07:6820:80da:af6b:8b2c]/records").is_ok());
+
+        assert!(check_url("ftp://example.com/records").is_err());
+        assert!(check_url("http://localhost/records").is_err());
+        assert!(check_url("http://127.0.0.1/records").is_err());
+        assert!(check_url("http://10.0.0.1/records").is_err());
+        assert!(check_url("http://192.168.1.10/records").is_err());
+        assert!(check_url("http://169.254.169.254/latest/meta-data").is_err());
+        assert!(check_url("http://100.64.0.1/records").is_err());
+        assert!(check_url("http://0.0.0.0/records").is_err());
+        assert!(check_url("http://[::1]/records").is_err());
+        assert!(check_url("http://[fd00:ec2::254]/records").is_err());
+        assert!(check_url("http://[fe80::1]/records").is_err());
+        assert!(check_url("http://[::ffff:127.0.0.1]/records").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_public_resolver_refuses_loopback() {
+        let name: Name = "localhost".parse().unwrap();
+        assert!(PublicResolver.resolve(name).await.is_err());
+    }
+}